@@ -29,3 +29,26 @@ end)
 
 -- A bash one-liner that displays the seconds since a target time.
 -- target="6:07:29 pm"; watch -n0.5 'echo $(($(date +%s) - $(date -d "'"$target"'" +%s)))'
+
+-- Controls for vrc-avpro-sucks. These are broadcast to every IPC client as client-message events.
+local function send_to_daemon(...)
+    mp.commandv("script-message", "vrc-avpro-sucks", ...)
+end
+
+-- alt+[ and alt+] nudge the sync offset, for when mpv is consistently ahead of or behind the ingame player
+mp.add_key_binding("alt+[", "offset_earlier", function()
+    send_to_daemon("offset", "-0.1")
+end, { repeatable = true })
+mp.add_key_binding("alt+]", "offset_later", function()
+    send_to_daemon("offset", "0.1")
+end, { repeatable = true })
+
+-- alt+d detaches from (or reattaches to) the ingame player
+mp.add_key_binding("alt+d", "toggle_attach", function()
+    send_to_daemon("toggle-attach")
+end)
+
+-- alt+r loads the video again from scratch, rather than just seeking it back into place
+mp.add_key_binding("alt+r", "reload", function()
+    send_to_daemon("reload")
+end)
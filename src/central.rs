@@ -1,42 +1,238 @@
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 use chrono::{DateTime, Local};
+use lazy_regex::{lazy_regex, Lazy};
 use regex::Regex;
 
 use crate::{
-    log_debug,
-    mpv_commander::{mpv_load_url, mpv_seek, MpvIpcRequest, MpvIpcResponse},
-    vrc_log_reader::{FoundSeek, UrlAndSeekResult, VrcLogReader, VrcLogWatcherEvent},
+    clock::Clock,
+    event_loop::earliest,
+    log_debug, log_error, log_info, log_warn,
+    persistence::{save_state, PersistedNowPlaying, PersistedState},
+    playback_backend::{PlaybackBackend, PlaybackEvent, PlayerExit},
+    vrc_log_reader::{LogMatcher, UrlAndSeekResult, VrcLogReader, VrcLogWatcherEvent},
 };
 
+// The first argument of every `script-message` our keybinds in seconds.lua send.
+const CLIENT_MESSAGE_TARGET: &str = "vrc-avpro-sucks";
+
+// If nothing but the log offset changed, don't bother rewriting the state file more often than this.
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+// When a backend reports its position, how far off it can be before we seek it back into place, unless configured
+// otherwise. VLC only reports whole seconds, so this can't be much tighter.
+const DEFAULT_MAX_DRIFT: f64 = 2.0;
+
+// Unless configured otherwise. Long enough to cover someone clicking through a playlist, and short next to how long it
+// takes to resolve a URL anyway.
+const DEFAULT_URL_DEBOUNCE: Duration = Duration::from_millis(500);
+
+// A video that should be this close to its end, or past it, is shown as ended, held this far from the end. Any closer,
+// and mpv may run off the end before it's shown anything.
+const END_MARGIN: f64 = 0.5;
+
+// The parts of the config that Central cares about.
+#[derive(Clone, Debug)]
+pub(crate) struct SyncSettings {
+    // What the manual offset starts out as.
+    pub(crate) offset: f64,
+    pub(crate) max_drift: f64,
+    // How long a new URL has to stay Now Playing before we load it.
+    pub(crate) url_debounce: Duration,
+    pub(crate) url_policy: UrlPolicy,
+    // The first one that matches the world we're in applies.
+    pub(crate) profiles: Vec<WorldProfile>,
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        Self {
+            offset: 0.0,
+            max_drift: DEFAULT_MAX_DRIFT,
+            url_debounce: DEFAULT_URL_DEBOUNCE,
+            url_policy: UrlPolicy::default(),
+            profiles: Vec::new(),
+        }
+    }
+}
+
+// Settings for particular worlds, which take over from the general ones while we're in one.
+#[derive(Clone, Debug)]
+pub(crate) struct WorldProfile {
+    // For the OSD.
+    pub(crate) name: String,
+    pub(crate) world_id: Option<String>,
+    pub(crate) world_name: Option<Regex>,
+    // Which of the world's ingame players to follow, on top of any player_name_regex from the command line.
+    pub(crate) player_name_regex: Option<Regex>,
+    // What the manual offset starts out as in this world.
+    pub(crate) offset: Option<f64>,
+    // Don't follow anything in this world.
+    pub(crate) disabled: bool,
+}
+
+impl WorldProfile {
+    // The index of the profile for `world`. Profiles for a particular world ID win over ones that match by name.
+    fn find(profiles: &[WorldProfile], world: &World) -> Option<usize> {
+        profiles
+            .iter()
+            .position(|profile| profile.world_id.as_deref() == Some(world.id.as_str()))
+            .or_else(|| {
+                profiles.iter().position(|profile| {
+                    profile.world_id.is_none()
+                        && profile.world_name.as_ref().is_some_and(|regex| {
+                            world
+                                .name
+                                .as_deref()
+                                .is_some_and(|name| regex.is_match(name))
+                        })
+                })
+            })
+    }
+
+    fn follows_player(&self, player_name: &str) -> bool {
+        self.player_name_regex
+            .as_ref()
+            .is_none_or(|regex| regex.is_match(player_name))
+    }
+}
+
+// Which URLs we're willing to load. With nothing in `allow`, everything that isn't denied is allowed.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct UrlPolicy {
+    pub(crate) allow: Vec<Regex>,
+    pub(crate) deny: Vec<Regex>,
+}
+
+impl UrlPolicy {
+    pub(crate) fn allows(&self, url: &str) -> bool {
+        !self.deny.iter().any(|regex| regex.is_match(url))
+            && (self.allow.is_empty() || self.allow.iter().any(|regex| regex.is_match(url)))
+    }
+}
+
+// The ways of linking to a YouTube video: youtu.be/ID, and youtube.com/watch?v=ID, /shorts/ID, /embed/ID or /live/ID, on
+// any subdomain.
+static YOUTUBE_URL_REGEX: Lazy<Regex> = lazy_regex!(
+    r"^(?:https?://)?(?:[\w-]+\.)?(?:youtu\.be/|youtube(?:-nocookie)?\.com/(?:watch\?(?:[^#]*&)?v=|shorts/|embed/|live/))(?P<id>[\w-]{11})(?:[^\w-]|$)"
+);
+
+// For telling whether two URLs are the same video. YouTube links boil down to the video's ID, and anything else has to
+// match exactly.
+pub(crate) fn normalize_url(url: &str) -> String {
+    match YOUTUBE_URL_REGEX.captures(url) {
+        Some(captures) => format!("youtube:{}", &captures["id"]),
+        None => url.to_string(),
+    }
+}
+
+// Streaming protocols, and Twitch channels, as opposed to their videos or clips. HLS can go either way, so for that, and
+// anything else, the player has to tell us once it's opened it.
+static LIVE_URL_REGEX: Lazy<Regex> = lazy_regex!(
+    r"^(?:(?:rtmps?|rtsp|srt)://|(?:https?://)?(?:www\.|m\.)?twitch\.tv/[\w-]+/?(?:[?#]|$))"
+);
+
+// Whether `url` is obviously a live stream, without having to open it first.
+pub(crate) fn is_live_url(url: &str) -> bool {
+    LIVE_URL_REGEX.is_match(url)
+}
+
 pub(crate) enum CentralCommand {
-    MpvIpcEvent(MpvIpcResponse),
+    PlaybackEvent(PlaybackEvent),
     VrcLogWatcherEvent(VrcLogWatcherEvent),
+    // The log watcher has handed out every complete line up to this byte offset.
+    VrcLogWatcherCheckpoint(u64),
+    // The config file changed, and this is what it says now.
+    ConfigReloaded(SyncSettings),
+    // From a signal, the control socket, or a thread that panicked. Central saves its state and closes the player. It
+    // shouldn't be given anything else after this.
+    Shutdown,
 }
 
 pub(crate) struct Central {
-    central_rx: Receiver<CentralCommand>,
-    pub(crate) central_tx: Sender<CentralCommand>,
-    mpv_ipc_tx: Sender<MpvIpcRequest>,
+    // mpv, usually.
+    backend: Box<dyn PlaybackBackend>,
+    clock: Box<dyn Clock>,
+    settings: SyncSettings,
 
     // When a URL becomes Now Playing, we'll load it into MPV.
     // But we have to wait for MPV to fully load the new URL before we can seek it properly.
-    // This structure holds any state for this in-between period.
-    // As soon as MPV reports back that it's loaded, this gets reset to None.
-    mid_loading_state: Option<MidLoadingState>,
+    // This is true for that in-between period. Seeks that come in meanwhile only update now_playing.
+    // As soon as MPV reports back that it's loaded, this gets reset to false.
+    is_loading: bool,
+
+    // Someone clicking through a playlist logs a URL for every video they skip past. Rather than have the player start
+    // on each of them, a new URL only gets loaded once it's been Now Playing for settings.url_debounce, going by
+    // Central's clock. Until then, now_playing is already the new URL, but the player still has the old one, so it's
+    // left alone.
+    load_at: Option<DateTime<Local>>,
+
+    // What the player was last told to load, if it still has it. When the ingame player announces the same video
+    // again, like when someone presses its reload button, seeking is all it takes.
+    loaded_url: Option<String>,
+
+    // How long the loaded video is, as far as the player has told us.
+    duration: Option<f64>,
+
+    // The ingame player is already done with the video, like when we join a world long after it started. Rather than
+    // play it from wherever, the player is paused on its last frame.
+    ended: bool,
+
+    // What's loaded is a live stream. There's no position in it to keep in sync, only the live edge, so it's never
+    // seeked, and the player is asked to keep latency down instead.
+    live: bool,
+    // What the player says about the loaded video, for telling whether it's live.
+    seekable: Option<bool>,
+    partially_seekable: Option<bool>,
+
+    // What the ingame player is playing, as far as we can tell from the log. This is kept up to date even while we're
+    // detached, so that we can resync when reattaching.
+    now_playing: Option<NowPlaying>,
+
+    // The ingame player whose URL we last loaded. Seeks from any other player in the world are about a different video.
+    followed_player: Option<String>,
+
+    // Added on top of every position we compute, for when MPV consistently runs ahead of or behind the ingame player.
+    manual_offset: f64,
+
+    // When detached, we keep reading the log, but leave MPV alone.
+    attached: bool,
+
+    // Where we are, as far as the log says.
+    world: Option<World>,
+    // Which of settings.profiles applies there, if any.
+    profile: Option<usize>,
+
+    log_path: PathBuf,
+    log_offset: u64,
+    // None when there's nothing worth saving, like during a replay.
+    state_file_path: Option<PathBuf>,
+    last_saved_state: Option<PersistedState>,
+    last_saved_at: Instant,
 }
 
 struct InitialStateResult {
     initial_state: Option<InitialState>,
-    lines_read_initially: u64,
+    bytes_read_initially: u64,
 }
 
 struct InitialState {
     timing_state: TimingState,
     url: String,
+    player_name: String,
 }
 
-struct MidLoadingState {
+struct World {
+    id: String,
+    // Logged a moment after the ID.
+    name: Option<String>,
+}
+
+struct NowPlaying {
+    url: String,
     timing_state: TimingState,
 }
 
@@ -47,126 +243,852 @@ struct TimingState {
 }
 
 impl Central {
-    pub(crate) fn new(mpv_ipc_tx: Sender<MpvIpcRequest>) -> Self {
-        let (central_tx, central_rx) = mpsc::channel::<CentralCommand>();
+    pub(crate) fn new(
+        log_path: PathBuf,
+        state_file_path: Option<PathBuf>,
+        clock: Box<dyn Clock>,
+        settings: SyncSettings,
+        mut backend: Box<dyn PlaybackBackend>,
+    ) -> Self {
+        // Open the mpv window right away, so it's clear that we're running, even if there's nothing to play yet.
+        // If we're attaching to an existing mpv instead, this connects to it.
+        backend.start();
         Self {
-            central_rx,
-            central_tx,
-            mpv_ipc_tx,
-            mid_loading_state: None,
+            backend,
+            clock,
+            is_loading: false,
+            load_at: None,
+            loaded_url: None,
+            duration: None,
+            ended: false,
+            live: false,
+            seekable: None,
+            partially_seekable: None,
+            now_playing: None,
+            followed_player: None,
+            manual_offset: settings.offset,
+            settings,
+            attached: true,
+            world: None,
+            profile: None,
+            log_path,
+            log_offset: 0,
+            state_file_path,
+            last_saved_state: None,
+            last_saved_at: Instant::now(),
         }
     }
 
-    pub(crate) fn do_initial(&mut self, player_name_regex: &Option<Regex>) -> u64 {
-        let initial_state_result = read_initial_state_from_log(player_name_regex);
+    pub(crate) fn do_initial(&mut self, matcher: &LogMatcher) -> u64 {
+        self.identify_world(matcher);
+        // The world's profile may narrow down which player we're looking for.
+        let matcher = matcher.with_player_name_regex(
+            self.profile()
+                .and_then(|profile| profile.player_name_regex.clone()),
+        );
+        let initial_state_result = read_initial_state_from_log(&self.log_path, &matcher);
 
         if let Some(initial_state) = initial_state_result.initial_state {
             log_debug!("Initial state found.");
             let timing_state = &initial_state.timing_state;
             log_debug!("Timestamp: {}", timing_state.timestamp);
             log_debug!("Additional offset: {}", timing_state.additional_offset);
-            log_debug!("Given that right now is {}", chrono::Local::now());
+            log_debug!("Given that right now is {}", self.clock.now());
             log_debug!(
                 "At this rate, we'll seek to {} (and counting), once MPV has loaded the file.",
-                calculate_seek_from_timing_state(&timing_state)
+                calculate_seek_from_timing_state(timing_state, self.clock.now())
             );
 
-            mpv_load_url(self.mpv_ipc_tx.clone(), &initial_state.url);
-            self.mid_loading_state = Some(MidLoadingState {
-                timing_state: timing_state.clone(),
+            self.followed_player = Some(initial_state.player_name);
+            if self.allows_url(&initial_state.url) {
+                self.now_playing = Some(NowPlaying {
+                    url: initial_state.url,
+                    timing_state: initial_state.timing_state,
+                });
+                if self.is_following() {
+                    self.load_now_playing();
+                }
+            }
+        }
+
+        self.log_offset = initial_state_result.bytes_read_initially;
+        self.save_state();
+        self.log_offset
+    }
+
+    // Picks up from a state saved by a previous run, instead of scanning the log. Returns the offset to watch from.
+    pub(crate) fn resume(&mut self, state: PersistedState, matcher: &LogMatcher) -> u64 {
+        log_debug!(
+            "Resuming from saved state at byte {} of {:#?}.",
+            state.log_offset,
+            state.log_path
+        );
+        // The profile's offset is only a starting point, so whatever it had been adjusted to wins.
+        self.identify_world(matcher);
+        self.followed_player = state.followed_player.clone();
+        self.manual_offset = state.manual_offset;
+        self.attached = state.attached;
+        // The config may have changed since.
+        self.now_playing = state
+            .now_playing
+            .as_ref()
+            .filter(|now_playing| self.allows_url(&now_playing.url))
+            .map(|now_playing| NowPlaying {
+                url: now_playing.url.clone(),
+                timing_state: TimingState {
+                    timestamp: now_playing.timestamp,
+                    additional_offset: now_playing.additional_offset,
+                },
             });
+        self.log_offset = state.log_offset;
+        self.last_saved_state = Some(state);
+
+        if self.is_following() {
+            self.load_now_playing();
         }
+        self.log_offset
+    }
 
-        initial_state_result.lines_read_initially
+    // Called by the event loop every time it wakes up, after everything else has had its turn. Lets the backend talk
+    // to the player, deals with whatever the player had to say, and saves the state if that's changed.
+    pub(crate) fn poll(&mut self) {
+        loop {
+            let events = self.backend.poll(Instant::now());
+            if events.is_empty() {
+                break;
+            }
+            for event in events {
+                self.handle_command(CentralCommand::PlaybackEvent(event));
+            }
+        }
+        if self
+            .load_at
+            .is_some_and(|load_at| self.clock.now() >= load_at)
+        {
+            self.load_now_playing();
+        }
+        self.save_state();
+    }
+
+    // When poll needs calling next, even if nothing else happens by then.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        earliest([
+            self.backend.next_deadline(),
+            self.load_at.map(|load_at| self.clock.instant_at(load_at)),
+            self.state_save_deadline(),
+        ])
+    }
+
+    // A one line summary, for the control socket.
+    pub(crate) fn status(&self) -> String {
+        let mut status = vec![if !self.attached {
+            "detached".to_string()
+        } else if self.is_following() {
+            "following".to_string()
+        } else {
+            "not following in this world".to_string()
+        }];
+        if let Some(player_name) = &self.followed_player {
+            status.push(format!("player {:?}", player_name));
+        }
+        match &self.now_playing {
+            Some(now_playing) => status.push(format!("playing {}", now_playing.url)),
+            None => status.push("nothing playing".to_string()),
+        }
+        if self.live {
+            status.push("live".to_string());
+        } else if let Some(position) = self.expected_position() {
+            status.push(format!("at {:.2}", position));
+        }
+        status.push(format!("offset {:+.2}s", self.manual_offset));
+        if let Some(profile) = self.profile() {
+            status.push(format!("profile {:?}", profile.name));
+        }
+        status.join(", ")
     }
 
-    pub(crate) fn run_central_dispatch(mut self) {
-        for response in self.central_rx {
-            match response {
-                CentralCommand::MpvIpcEvent(MpvIpcResponse::FileLoaded) => {
-                    // We're technically allowed to start seeking as soon as the FileLoaded event, which happens earlier
-                    // in the cycle than PlaybackRestart. But by the time MPV starts playback, which could be several
-                    // seconds, our target_timestamp will become stale.
-                    // So let's wait until MPV is actually rolling.
+    pub(crate) fn handle_command(&mut self, command: CentralCommand) {
+        match command {
+            CentralCommand::PlaybackEvent(PlaybackEvent::FileLoaded) => {
+                // We're technically allowed to start seeking as soon as the FileLoaded event, which happens earlier
+                // in the cycle than PlaybackRestart. But by the time MPV starts playback, which could be several
+                // seconds, our target_timestamp will become stale.
+                // So let's wait until MPV is actually rolling.
+            }
+            CentralCommand::PlaybackEvent(PlaybackEvent::PlaybackRestart) => {
+                if self.is_loading {
+                    self.is_loading = false;
+                    // We were waiting on MPV to load the file. We're finally allowed to seek, unless it's about to be
+                    // told to load something else.
+                    if self.load_at.is_none() {
+                        self.seek_to_expected_position();
+                    }
+                }
+            }
+            CentralCommand::PlaybackEvent(PlaybackEvent::FileEnded) => {
+                // The player has nothing loaded anymore, so if the same video is announced again, it has to be loaded
+                // again, rather than seeked.
+                self.loaded_url = None;
+                self.duration = None;
+                self.ended = false;
+            }
+            CentralCommand::PlaybackEvent(PlaybackEvent::Position(position)) => {
+                // Once it's ended, the player's meant to be stuck where it is.
+                if !self.is_ready() || self.live || self.ended || !self.should_control_backend() {
+                    return;
+                }
+                let Some(expected_position) = self.expected_position() else {
+                    return;
+                };
+                let drift = position - expected_position;
+                if drift.abs() > self.settings.max_drift {
+                    self.explain(format!(
+                        "playback is at {}, but should be at {:.2}, {:+.2}s off, which is more than {}s, so seeking",
+                        position, expected_position, drift, self.settings.max_drift
+                    ));
+                    // Which might be past the end by now.
+                    self.seek_to_expected_position();
+                }
+            }
+            CentralCommand::PlaybackEvent(PlaybackEvent::Duration(duration)) => {
+                self.duration = duration;
+                // If the player took a moment to say, it may already be playing past where the video should've ended.
+                let past_the_end = duration.is_some_and(|duration| {
+                    self.expected_position()
+                        .is_some_and(|position| position > duration - END_MARGIN)
+                });
+                if past_the_end && !self.ended && self.is_following() && self.is_ready() {
+                    self.seek_to_expected_position();
+                }
+            }
+            CentralCommand::PlaybackEvent(PlaybackEvent::Seekable(seekable)) => {
+                self.seekable = seekable;
+                self.check_for_live_stream();
+            }
+            CentralCommand::PlaybackEvent(PlaybackEvent::PartiallySeekable(partially_seekable)) => {
+                self.partially_seekable = partially_seekable;
+                self.check_for_live_stream();
+            }
+            CentralCommand::PlaybackEvent(PlaybackEvent::ClientMessage(args)) => {
+                self.handle_client_message(&args);
+            }
+            CentralCommand::PlaybackEvent(PlaybackEvent::Disconnected(generation)) => {
+                self.handle_mpv_disconnect(generation);
+            }
+            CentralCommand::VrcLogWatcherEvent(VrcLogWatcherEvent::FoundUrl(found_url)) => {
+                if let Some(profile) = self.profile() {
+                    if !profile.follows_player(&found_url.player_name) {
+                        let reason = format!(
+                            "ignoring {} from {:?}, since profile {:?} doesn't follow it",
+                            found_url.url, found_url.player_name, profile.name
+                        );
+                        self.explain(reason);
+                        return;
+                    }
+                }
+                if self.followed_player.as_ref() != Some(&found_url.player_name) {
+                    self.explain(format!("following {:?}", found_url.player_name));
+                }
+                self.followed_player = Some(found_url.player_name);
+
+                if !self.allows_url(&found_url.url) {
+                    // Whatever we were playing isn't what the ingame player is playing anymore.
+                    if self.should_control_backend() {
+                        self.backend.stop();
+                        self.set_live(false);
+                    }
+                    self.is_loading = false;
+                    self.load_at = None;
+                    self.loaded_url = None;
+                    self.now_playing = None;
+                    return;
+                }
+
+                if self.load_at.is_some() {
+                    if let Some(now_playing) = &self.now_playing {
+                        let reason = format!(
+                            "not loading {}, since {} replaced it within {}s",
+                            now_playing.url,
+                            found_url.url,
+                            self.settings.url_debounce.as_secs_f64()
+                        );
+                        self.explain(reason);
+                    }
+                }
+
+                // By the time this video loads in MPV, several seconds will likely have passed.
+                // Let's say the clock starts ticking right when the log watcher reports FoundUrl.
+                // FIXME: Though maybe it'd be better to wait for _TvPlay? Research needed.
+                let already_loaded = self.has_loaded(&found_url.url);
+                self.now_playing = Some(NowPlaying {
+                    url: found_url.url,
+                    timing_state: TimingState {
+                        timestamp: found_url.timestamp,
+                        // Ingame players start playing new content from the beginning.
+                        additional_offset: 0.0,
+                    },
+                });
+                if !self.is_following() {
+                    return;
                 }
-                CentralCommand::MpvIpcEvent(MpvIpcResponse::PlaybackRestart) => {
-                    if let Some(state) = self.mid_loading_state {
-                        self.mid_loading_state = None;
-                        // We were waiting on MPV to load the file. We're finally allowed to seek.
-                        let target_timestamp =
-                            calculate_seek_from_timing_state(&state.timing_state);
-                        mpv_seek(self.mpv_ipc_tx.clone(), target_timestamp);
+                if already_loaded {
+                    self.explain(
+                        "the player already has this video, so seeking instead of reloading"
+                            .to_string(),
+                    );
+                    // Whatever came in between doesn't matter anymore.
+                    self.load_at = None;
+                    // If it's still loading, it's seeked once it's done.
+                    if self.is_ready() {
+                        self.seek_to_expected_position();
                     }
+                } else {
+                    self.load_now_playing_soon();
                 }
-                CentralCommand::VrcLogWatcherEvent(VrcLogWatcherEvent::FoundUrl(found_url)) => {
-                    mpv_load_url(self.mpv_ipc_tx.clone(), &found_url.url);
-
-                    // By the time this video loads in MPV, several seconds will likely have passed.
-                    // Let's say the clock starts ticking right when the log watcher reports FoundUrl.
-                    // FIXME: Though maybe it'd be better to wait for _TvPlay? Research needed.
-                    self.mid_loading_state = Some(MidLoadingState {
-                        timing_state: TimingState {
-                            timestamp: found_url.timestamp,
-                            // Ingame players start playing new content from the beginning.
-                            additional_offset: 0.0,
-                        },
-                    });
-                }
-                CentralCommand::VrcLogWatcherEvent(VrcLogWatcherEvent::FoundSeek(found_seek)) => {
-                    if let Some(state) = self.mid_loading_state {
-                        // We're still loading, so trying to seek now would be ignored.
-                        // Let's just update the mid-loading state with this new, fresher seek estimate.
-                        self.mid_loading_state = Some(MidLoadingState {
-                            timing_state: TimingState {
-                                timestamp: found_seek.timestamp,
-                                additional_offset: found_seek.seek_offset,
-                            },
-                            ..state
-                        });
-                    } else {
-                        // MPV is loaded. Seeks are allowed.
-                        let new_timestamp = timestamp_from_seek_line(&found_seek);
-                        mpv_seek(self.mpv_ipc_tx.clone(), new_timestamp);
+            }
+            CentralCommand::VrcLogWatcherEvent(VrcLogWatcherEvent::FoundSeek(found_seek)) => {
+                if self.followed_player.as_ref() != Some(&found_seek.player_name) {
+                    self.explain(format!(
+                        "ignoring seek to {} from {:?}, since we're following {:?}",
+                        found_seek.seek_offset, found_seek.player_name, self.followed_player
+                    ));
+                    return;
+                }
+                let Some(now_playing) = &mut self.now_playing else {
+                    return;
+                };
+
+                // Whether or not we act on it now, this is the freshest estimate of where the video is.
+                now_playing.timing_state = TimingState {
+                    timestamp: found_seek.timestamp,
+                    additional_offset: found_seek.seek_offset,
+                };
+
+                // If we're still loading, trying to seek now would be ignored. We'll seek once MPV is ready.
+                if self.is_following() && self.is_ready() {
+                    // MPV is loaded. Seeks are allowed.
+                    self.seek_to_expected_position();
+                }
+            }
+            CentralCommand::VrcLogWatcherEvent(VrcLogWatcherEvent::FoundWorldChange(
+                found_world_change,
+            )) => {
+                log_debug!(
+                    "Joined world {} at {}, so resetting state.",
+                    found_world_change.world_id,
+                    found_world_change.timestamp
+                );
+                // Whatever was playing belongs to the world we just left.
+                if self.should_control_backend() {
+                    self.backend.stop();
+                    self.set_live(false);
+                    if self.ended {
+                        self.ended = false;
+                        self.backend.set_paused(false);
                     }
                 }
+                // If we're leaving the player alone, and it's held on the last frame, it's unpaused once there's
+                // something new to play.
+                self.is_loading = false;
+                self.load_at = None;
+                self.loaded_url = None;
+                self.duration = None;
+                self.now_playing = None;
+                self.followed_player = None;
+                self.world = Some(World {
+                    id: found_world_change.world_id,
+                    name: None,
+                });
+                self.select_profile();
+            }
+            CentralCommand::VrcLogWatcherEvent(VrcLogWatcherEvent::FoundWorldName(
+                found_world_name,
+            )) => {
+                log_debug!(
+                    "The world is called {:?}, as of {}.",
+                    found_world_name.world_name,
+                    found_world_name.timestamp
+                );
+                if let Some(world) = &mut self.world {
+                    world.name = Some(found_world_name.world_name);
+                    self.select_profile();
+                }
+            }
+            CentralCommand::VrcLogWatcherCheckpoint(offset) => {
+                self.log_offset = offset;
+            }
+            CentralCommand::ConfigReloaded(settings) => {
+                self.apply_settings(settings);
+            }
+            CentralCommand::Shutdown => {
+                log_info!("Shutting down.");
+                // However recently we last saved, the latest log offset is worth keeping.
+                self.last_saved_state = None;
+                self.save_state();
+                self.backend.shutdown();
+            }
+        }
+    }
+
+    fn handle_client_message(&mut self, args: &[String]) {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match args.as_slice() {
+            [CLIENT_MESSAGE_TARGET, "offset", delta] => {
+                let Ok(delta) = delta.parse::<f64>() else {
+                    log_warn!("Invalid offset adjustment from MPV: {:#?}", delta);
+                    return;
+                };
+                self.adjust_offset(delta);
+            }
+            [CLIENT_MESSAGE_TARGET, "toggle-attach"] => {
+                self.toggle_attach();
+            }
+            [CLIENT_MESSAGE_TARGET, "reload"] => {
+                self.reload();
+            }
+            [CLIENT_MESSAGE_TARGET, ..] => {
+                log_warn!("Unknown message from MPV: {:?}", args);
+            }
+            // meant for some other script
+            _ => {}
+        }
+    }
+
+    // From the keybinds in mpv, or the control socket.
+    pub(crate) fn adjust_offset(&mut self, delta: f64) {
+        self.manual_offset += delta;
+        log_info!("Manual offset is now {:+.2}s", self.manual_offset);
+        self.show_text(&format!("Offset: {:+.2}s", self.manual_offset));
+        if self.is_following() && self.is_ready() {
+            self.seek_to_expected_position();
+        }
+    }
+
+    pub(crate) fn toggle_attach(&mut self) {
+        self.attached = !self.attached;
+        if self.attached {
+            log_info!("Reattached to VRChat, so resyncing.");
+            self.show_text("Following VRChat");
+            if self.is_following() {
+                self.load_now_playing();
+            }
+        } else {
+            log_info!("Detached from VRChat.");
+            self.show_text("Detached from VRChat");
+            self.is_loading = false;
+            self.load_at = None;
+        }
+    }
+
+    // Loads what's playing from scratch, even if the player already has it. For when a video got stuck, or yt-dlp picked
+    // a bad format.
+    pub(crate) fn reload(&mut self) {
+        let Some(url) = self
+            .now_playing
+            .as_ref()
+            .map(|now_playing| now_playing.url.clone())
+            .filter(|_| self.is_following())
+        else {
+            self.explain("nothing to reload".to_string());
+            return;
+        };
+        log_info!("Reloading, as asked.");
+        self.show_text("Reloading");
+        // If the player found out it's live, there's no need for it to find out all over again.
+        let live = is_live_url(&url) || (self.live && self.has_loaded(&url));
+        self.load_at = None;
+        self.load(url, live);
+    }
+
+    fn handle_mpv_disconnect(&mut self, generation: u64) {
+        let Some(exit) = self.backend.handle_disconnect(generation) else {
+            // we already know about this one
+            return;
+        };
+        // Whatever mpv had, or was loading, or was about to, is gone with it.
+        self.is_loading = false;
+        self.load_at = None;
+        self.loaded_url = None;
+        self.duration = None;
+        self.ended = false;
+        // A new player doesn't have the badge.
+        self.live = false;
+
+        match exit {
+            PlayerExit::Closed => {
+                log_info!("mpv was closed. We'll bring it back once there's a new video.");
+            }
+            PlayerExit::CrashedOnStartup => {
+                log_error!(
+                    "mpv died right after starting. We'll try again once there's a new video."
+                );
+            }
+            PlayerExit::Crashed => {
+                if self.is_following() && self.now_playing.is_some() {
+                    log_warn!("mpv died, so restarting it and reloading the video.");
+                    // Once it's loaded, this seeks to wherever the video should be by then.
+                    self.load_now_playing();
+                } else {
+                    log_warn!("mpv died. We'll start it again once there's a new video.");
+                }
             }
         }
     }
-}
 
-fn timestamp_from_seek_line(found_seek: &FoundSeek) -> f64 {
-    // how long has it been since this timestamp?
-    let now = chrono::Local::now();
-    let duration = now.signed_duration_since(found_seek.timestamp);
-    // add this duration to the seek offset, which has also been returned from the log file
-    let new_seek_offset = found_seek.seek_offset + duration.num_milliseconds() as f64 / 1000.0;
+    // Only while mpv is running and we're following. We don't start mpv just to tell it to do something.
+    fn should_control_backend(&self) -> bool {
+        self.is_following() && self.backend.is_running()
+    }
+
+    // Attached, and not in a world whose profile says to leave it alone.
+    fn is_following(&self) -> bool {
+        self.attached && !self.profile().is_some_and(|profile| profile.disabled)
+    }
+
+    fn profile(&self) -> Option<&WorldProfile> {
+        self.profile.map(|index| &self.settings.profiles[index])
+    }
+
+    // Works out which world we're in from the log, for when we start up in the middle of it.
+    fn identify_world(&mut self, matcher: &LogMatcher) {
+        let mut vlr = VrcLogReader::new(self.log_path.clone(), matcher.clone());
+        if let Some((found_world_change, world_name)) = vlr.get_current_world() {
+            self.world = Some(World {
+                id: found_world_change.world_id,
+                name: world_name,
+            });
+            self.select_profile();
+        }
+    }
+
+    // Picks the profile for the world we're in, and if that's a different one than before, starts the manual offset
+    // over from the new profile's.
+    fn select_profile(&mut self) {
+        let profile = self
+            .world
+            .as_ref()
+            .and_then(|world| WorldProfile::find(&self.settings.profiles, world));
+        if profile == self.profile {
+            return;
+        }
+        self.profile = profile;
+        self.manual_offset = self.starting_offset();
+        self.announce_profile();
+    }
+
+    // What the manual offset starts out as, in the world we're in.
+    fn starting_offset(&self) -> f64 {
+        self.profile()
+            .and_then(|profile| profile.offset)
+            .unwrap_or(self.settings.offset)
+    }
+
+    fn announce_profile(&mut self) {
+        let Some(profile) = self.profile() else {
+            self.explain("no profile for this world".to_string());
+            self.show_text("No profile");
+            return;
+        };
+        let text = if profile.disabled {
+            format!("Profile: {} (not following)", profile.name)
+        } else {
+            format!("Profile: {}", profile.name)
+        };
+        self.explain(format!(
+            "using profile {:?}, with a manual offset of {:+.2}s",
+            profile.name, self.manual_offset
+        ));
+        self.show_text(&text);
+    }
+
+    // Swaps in a reloaded config, without losing track of what's playing. Profiles are matched up by name, since the
+    // one we're using may have moved around in the list.
+    fn apply_settings(&mut self, settings: SyncSettings) {
+        let old_profile_name = self.profile().map(|profile| profile.name.clone());
+        let old_starting_offset = self.starting_offset();
+        self.settings = settings;
+        self.profile = self
+            .world
+            .as_ref()
+            .and_then(|world| WorldProfile::find(&self.settings.profiles, world));
+
+        let new_profile_name = self.profile().map(|profile| profile.name.clone());
+        let offset_changed = if new_profile_name != old_profile_name {
+            self.manual_offset = self.starting_offset();
+            self.announce_profile();
+            true
+        } else if self.starting_offset() != old_starting_offset {
+            // Adjustments made since are lost, but whoever edited the offset presumably wants that one.
+            self.manual_offset = self.starting_offset();
+            self.explain(format!(
+                "the config changed the manual offset to {:+.2}s",
+                self.manual_offset
+            ));
+            self.show_text(&format!("Offset: {:+.2}s", self.manual_offset));
+            true
+        } else {
+            false
+        };
+
+        let Some(url) = self
+            .now_playing
+            .as_ref()
+            .map(|now_playing| now_playing.url.clone())
+        else {
+            return;
+        };
+        if !self.allows_url(&url) {
+            if self.should_control_backend() {
+                self.backend.stop();
+                self.set_live(false);
+            }
+            self.is_loading = false;
+            self.load_at = None;
+            self.loaded_url = None;
+            self.now_playing = None;
+        } else if offset_changed && self.is_following() && self.is_ready() {
+            self.seek_to_expected_position();
+        }
+    }
+
+    fn show_text(&mut self, text: &str) {
+        if self.backend.is_running() {
+            self.backend.show_text(text);
+        }
+    }
+
+    // Whether the player has what's Now Playing, loaded and rolling, so that it can be seeked.
+    fn is_ready(&self) -> bool {
+        !self.is_loading && self.load_at.is_none()
+    }
+
+    // For a URL that's just been found, which may only be one of many in a row.
+    fn load_now_playing_soon(&mut self) {
+        if self.settings.url_debounce.is_zero() {
+            self.load_now_playing();
+            return;
+        }
+        self.load_at = Some(
+            self.clock.now()
+                + chrono::Duration::from_std(self.settings.url_debounce).unwrap_or_default(),
+        );
+    }
+
+    fn has_loaded(&self, url: &str) -> bool {
+        self.loaded_url
+            .as_deref()
+            .is_some_and(|loaded_url| normalize_url(loaded_url) == normalize_url(url))
+    }
+
+    fn load_now_playing(&mut self) {
+        self.load_at = None;
+        if let Some(url) = self
+            .now_playing
+            .as_ref()
+            .map(|now_playing| now_playing.url.clone())
+        {
+            let live = is_live_url(&url);
+            self.load(url, live);
+        }
+    }
+
+    // Loads `url` from scratch, whether or not the player already has it.
+    fn load(&mut self, url: String, live: bool) {
+        if live {
+            self.backend.load_live(&url);
+        } else {
+            self.backend.load(&url);
+        }
+        self.loaded_url = Some(url);
+        self.is_loading = true;
+        self.duration = None;
+        self.seekable = None;
+        self.partially_seekable = None;
+        // The player stays paused from one video to the next.
+        if self.ended {
+            self.ended = false;
+            self.backend.set_paused(false);
+        }
+        self.set_live(live);
+    }
+
+    fn set_live(&mut self, live: bool) {
+        if live == self.live {
+            return;
+        }
+        self.live = live;
+        if live {
+            self.explain("it's a live stream, so it won't be seeked".to_string());
+        }
+        if self.backend.is_running() {
+            self.backend.show_badge(live.then_some("LIVE"));
+        }
+    }
+
+    // A live stream can't be seeked, or only within what the player has cached. If the URL didn't give that away, the
+    // player is told now. Plenty of videos that aren't live are only partially seekable too, e.g. over HLS, but those
+    // have a length.
+    fn check_for_live_stream(&mut self) {
+        let player_says_live = self.seekable == Some(false)
+            || (self.partially_seekable == Some(true) && self.duration.is_none());
+        if !player_says_live || self.live || self.load_at.is_some() || !self.is_following() {
+            return;
+        }
+        let Some(url) = &self.loaded_url else {
+            return;
+        };
+        self.explain(format!("the player says {} is a live stream", url));
+        self.backend.go_live();
+        self.set_live(true);
+    }
+
+    // Do this as late as possible.
+    fn seek_to_expected_position(&mut self) {
+        if !self.should_control_backend() || self.live {
+            return;
+        }
+        let Some(now_playing) = &self.now_playing else {
+            return;
+        };
+        let timing_state = &now_playing.timing_state;
+        let elapsed = calculate_seek_from_timing_state(timing_state, self.clock.now())
+            - timing_state.additional_offset;
+        let target_timestamp = timing_state.additional_offset + elapsed + self.manual_offset;
+        if let Some(duration) = self.duration {
+            if target_timestamp > duration - END_MARGIN {
+                self.show_ended(target_timestamp, duration);
+                return;
+            }
+        }
+        self.explain(format!(
+            "target {:.3} = {} as of {}, {:+.3}s since, {:+.2}s manual offset",
+            target_timestamp,
+            timing_state.additional_offset,
+            timing_state.timestamp.format("%H:%M:%S"),
+            elapsed,
+            self.manual_offset
+        ));
+        if self.ended {
+            self.ended = false;
+            self.backend.set_paused(false);
+        }
+        self.backend.seek(target_timestamp);
+    }
+
+    // Holds the player on the video's last frame, since the ingame player's done with it.
+    fn show_ended(&mut self, target_timestamp: f64, duration: f64) {
+        if self.ended {
+            return;
+        }
+        self.explain(format!(
+            "target {:.3} is past the end of the video, at {:.3}, so showing it as ended",
+            target_timestamp, duration
+        ));
+        self.ended = true;
+        self.backend.set_paused(true);
+        self.backend.seek((duration - END_MARGIN).max(0.0));
+        self.show_text("Ended");
+    }
+
+    fn allows_url(&mut self, url: &str) -> bool {
+        let allowed = self.settings.url_policy.allows(url);
+        if !allowed {
+            self.explain(format!(
+                "not loading {}, since the URL policy doesn't allow it",
+                url
+            ));
+        }
+        allowed
+    }
+
+    // Goes to the terminal, and to the backend, in case it has nowhere better to show it.
+    fn explain(&mut self, reason: String) {
+        log_info!("Decision: {}", reason);
+        self.backend.explain(&reason);
+    }
+
+    // Where the video should be right now, if there's one playing.
+    fn expected_position(&self) -> Option<f64> {
+        self.now_playing.as_ref().map(|now_playing| {
+            calculate_seek_from_timing_state(&now_playing.timing_state, self.clock.now())
+                + self.manual_offset
+        })
+    }
 
-    new_seek_offset
+    fn snapshot_state(&self) -> PersistedState {
+        PersistedState {
+            log_path: self.log_path.clone(),
+            log_offset: self.log_offset,
+            followed_player: self.followed_player.clone(),
+            manual_offset: self.manual_offset,
+            attached: self.attached,
+            now_playing: self
+                .now_playing
+                .as_ref()
+                .map(|now_playing| PersistedNowPlaying {
+                    url: now_playing.url.clone(),
+                    timestamp: now_playing.timing_state.timestamp,
+                    additional_offset: now_playing.timing_state.additional_offset,
+                }),
+        }
+    }
+
+    // If only the log offset has changed since we last saved, it's saved once STATE_SAVE_INTERVAL is up, even if nothing
+    // else happens meanwhile.
+    fn state_save_deadline(&self) -> Option<Instant> {
+        self.state_file_path.as_ref()?;
+        self.last_saved_state
+            .as_ref()
+            .filter(|last_saved_state| last_saved_state.log_offset != self.log_offset)
+            .map(|_| self.last_saved_at + STATE_SAVE_INTERVAL)
+    }
+
+    fn save_state(&mut self) {
+        let Some(state_file_path) = &self.state_file_path else {
+            return;
+        };
+        let state = self.snapshot_state();
+        if let Some(last_saved_state) = &self.last_saved_state {
+            if *last_saved_state == state {
+                return;
+            }
+            let only_offset_changed = PersistedState {
+                log_offset: last_saved_state.log_offset,
+                ..state.clone()
+            } == *last_saved_state;
+            if only_offset_changed && self.last_saved_at.elapsed() < STATE_SAVE_INTERVAL {
+                return;
+            }
+        }
+
+        if let Err(e) = save_state(state_file_path, &state) {
+            log_error!("Failed to save state: {}", e);
+        }
+        self.last_saved_state = Some(state);
+        self.last_saved_at = Instant::now();
+    }
 }
 
 // Do this as late as possible.
-fn calculate_seek_from_timing_state(state: &TimingState) -> f64 {
+fn calculate_seek_from_timing_state(state: &TimingState, now: DateTime<Local>) -> f64 {
     // how long has it been since this timestamp?
-    let now = chrono::Local::now();
     let duration = now.signed_duration_since(state.timestamp);
     // add this duration to the seek offset, which may have also been returned from the log file
-    let new_seek_offset = state.additional_offset + duration.num_milliseconds() as f64 / 1000.0;
-
-    new_seek_offset
+    state.additional_offset + duration.num_milliseconds() as f64 / 1000.0
 }
 
-fn read_initial_state_from_log(player_name_regex: &Option<Regex>) -> InitialStateResult {
-    let mut vlr = VrcLogReader::from_latest(player_name_regex);
+fn read_initial_state_from_log(log_path: &Path, matcher: &LogMatcher) -> InitialStateResult {
+    let mut vlr = VrcLogReader::new(log_path.to_path_buf(), matcher.clone());
     let url_and_seek = vlr.get_latest_url_and_seek();
     match url_and_seek {
-        UrlAndSeekResult::Nothing(lines_read_initially) => {
+        UrlAndSeekResult::Nothing(bytes_read_initially) => {
             log_debug!("No URL found in the log file so far. We'll wait for some.");
             InitialStateResult {
                 initial_state: None,
-                lines_read_initially,
+                bytes_read_initially,
             }
         }
-        UrlAndSeekResult::Url(found_url, lines_read_initially) => {
+        UrlAndSeekResult::Url(found_url, bytes_read_initially) => {
             log_debug!("URL found: {}", found_url.url);
 
             InitialStateResult {
@@ -176,11 +1098,12 @@ fn read_initial_state_from_log(player_name_regex: &Option<Regex>) -> InitialStat
                         additional_offset: 0.0,
                     },
                     url: found_url.url,
+                    player_name: found_url.player_name,
                 }),
-                lines_read_initially,
+                bytes_read_initially,
             }
         }
-        UrlAndSeekResult::UrlAndSeek(found_url, found_seek, lines_read_initially) => {
+        UrlAndSeekResult::UrlAndSeek(found_url, found_seek, bytes_read_initially) => {
             InitialStateResult {
                 initial_state: Some(InitialState {
                     timing_state: TimingState {
@@ -188,8 +1111,9 @@ fn read_initial_state_from_log(player_name_regex: &Option<Regex>) -> InitialStat
                         additional_offset: found_seek.seek_offset,
                     },
                     url: found_url.url,
+                    player_name: found_url.player_name,
                 }),
-                lines_read_initially,
+                bytes_read_initially,
             }
         }
     }
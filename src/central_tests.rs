@@ -0,0 +1,919 @@
+#[cfg(test)]
+mod central {
+    use std::{cell::Cell, rc::Rc, time::Instant};
+
+    use chrono::{DateTime, Duration, Local};
+    use regex::Regex;
+    use tempfile::TempDir;
+
+    use crate::{
+        central::{
+            is_live_url, normalize_url, Central, CentralCommand, SyncSettings, UrlPolicy,
+            WorldProfile,
+        },
+        clock::{Clock, SystemClock},
+        mock_backend::{BackendCall, MockBackend},
+        persistence::{load_state, PersistedNowPlaying, PersistedState},
+        playback_backend::{PlaybackEvent, PlayerExit},
+        vrc_log_reader::{
+            builtin_player_definitions, FoundSeek, FoundUrl, FoundWorldChange, FoundWorldName,
+            LogMatcher, VrcLogWatcherEvent,
+        },
+    };
+
+    const URL: &str = "https://youtu.be/zL3wWykAKfs";
+    const PLAYER: &str = "ProTV Hangout (1)";
+    const WORLD: &str = "wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd";
+
+    // Only moves when told to. Clones share the same time, so a test can keep one while Central owns the other.
+    #[derive(Clone)]
+    struct ManualClock(Rc<Cell<DateTime<Local>>>);
+
+    impl ManualClock {
+        fn new() -> Self {
+            Self(Rc::new(Cell::new(Local::now())))
+        }
+
+        fn advance(&self, milliseconds: i64) {
+            self.0
+                .set(self.0.get() + Duration::milliseconds(milliseconds));
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> DateTime<Local> {
+            self.0.get()
+        }
+
+        fn instant_at(&self, at: DateTime<Local>) -> Instant {
+            Instant::now()
+                + at.signed_duration_since(self.now())
+                    .to_std()
+                    .unwrap_or_default()
+        }
+    }
+
+    // Loading every URL right away keeps the tests that aren't about debouncing simple.
+    fn settings() -> SyncSettings {
+        SyncSettings {
+            url_debounce: std::time::Duration::ZERO,
+            ..SyncSettings::default()
+        }
+    }
+
+    fn new_central() -> (Central, MockBackend, TempDir) {
+        new_central_with(settings())
+    }
+
+    fn new_central_with(settings: SyncSettings) -> (Central, MockBackend, TempDir) {
+        new_central_with_clock(settings, Box::new(SystemClock))
+    }
+
+    fn new_central_with_clock(
+        settings: SyncSettings,
+        clock: Box<dyn Clock>,
+    ) -> (Central, MockBackend, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = MockBackend::default();
+        let central = Central::new(
+            dir.path().join("output_log.txt"),
+            Some(dir.path().join("state.json")),
+            clock,
+            settings,
+            Box::new(backend.clone()),
+        );
+        assert_eq!(backend.take_calls(), vec![BackendCall::Start]);
+        (central, backend, dir)
+    }
+
+    // `seconds_ago` is when the line was logged.
+    fn found_url(url: &str, player_name: &str, seconds_ago: i64) -> CentralCommand {
+        CentralCommand::VrcLogWatcherEvent(VrcLogWatcherEvent::FoundUrl(FoundUrl {
+            timestamp: Local::now() - Duration::seconds(seconds_ago),
+            url: url.to_string(),
+            player_name: player_name.to_string(),
+        }))
+    }
+
+    fn found_seek(seek_offset: f64, player_name: &str, seconds_ago: i64) -> CentralCommand {
+        CentralCommand::VrcLogWatcherEvent(VrcLogWatcherEvent::FoundSeek(FoundSeek {
+            timestamp: Local::now() - Duration::seconds(seconds_ago),
+            seek_offset,
+            player_name: player_name.to_string(),
+        }))
+    }
+
+    fn playback_restart() -> CentralCommand {
+        CentralCommand::PlaybackEvent(PlaybackEvent::PlaybackRestart)
+    }
+
+    // Seeks depend on the current time, so they can't be compared exactly.
+    fn assert_seeked_to(calls: &[BackendCall], expected: f64) {
+        match calls {
+            [BackendCall::Seek(position)] => assert!(
+                (position - expected).abs() < 0.5,
+                "seeked to {}, expected about {}",
+                position,
+                expected
+            ),
+            _ => panic!("expected a single seek, got {:?}", calls),
+        }
+    }
+
+    #[test]
+    fn it_seeks_once_playback_starts() {
+        let (mut central, backend, _dir) = new_central();
+
+        central.handle_command(found_url(URL, PLAYER, 10));
+        assert_eq!(
+            backend.take_calls(),
+            vec![BackendCall::Load(URL.to_string())]
+        );
+
+        // still loading, so this only updates where we should be
+        central.handle_command(found_seek(100.0, PLAYER, 3));
+        assert_eq!(backend.take_calls(), vec![]);
+
+        central.handle_command(playback_restart());
+        assert_seeked_to(&backend.take_calls(), 103.0);
+
+        // once loaded, seeks apply right away
+        central.handle_command(found_seek(200.0, PLAYER, 0));
+        assert_seeked_to(&backend.take_calls(), 200.0);
+    }
+
+    #[test]
+    fn it_ignores_seeks_from_other_players() {
+        let (mut central, backend, _dir) = new_central();
+
+        central.handle_command(found_url(URL, PLAYER, 0));
+        central.handle_command(playback_restart());
+        backend.take_calls();
+
+        central.handle_command(found_seek(100.0, "ProTV Lobby", 0));
+        assert_eq!(backend.take_calls(), vec![]);
+    }
+
+    #[test]
+    fn it_stops_on_world_change() {
+        let (mut central, backend, _dir) = new_central();
+
+        central.handle_command(found_url(URL, PLAYER, 0));
+        central.handle_command(playback_restart());
+        backend.take_calls();
+
+        central.handle_command(CentralCommand::VrcLogWatcherEvent(
+            VrcLogWatcherEvent::FoundWorldChange(FoundWorldChange {
+                timestamp: Local::now(),
+                world_id: "wrld_4cf554b4-430c-4f8f-b53e-1f294eed230b".to_string(),
+            }),
+        ));
+        assert_eq!(backend.take_calls(), vec![BackendCall::Stop]);
+
+        // the old world's player is forgotten
+        central.handle_command(found_seek(100.0, PLAYER, 0));
+        assert_eq!(backend.take_calls(), vec![]);
+    }
+
+    #[test]
+    fn it_starts_over_in_a_new_world() {
+        let clock = ManualClock::new();
+        let (mut central, backend, _dir) = new_central_with_clock(
+            SyncSettings {
+                url_debounce: std::time::Duration::from_secs(1),
+                ..SyncSettings::default()
+            },
+            Box::new(clock.clone()),
+        );
+        const OTHER_PLAYER: &str = "Theatre 1 TVManager";
+
+        // a video about to be loaded, when we leave
+        central.handle_command(found_url(URL, PLAYER, 0));
+        central.handle_command(joined(WORLD));
+        clock.advance(2_000);
+        central.poll();
+        assert_eq!(backend.take_calls(), vec![BackendCall::Stop]);
+        assert!(central.status().contains("nothing playing"));
+
+        // whichever player in the new world starts something first is followed, even if it's the same video
+        central.handle_command(found_seek(100.0, PLAYER, 0));
+        central.handle_command(found_url(URL, OTHER_PLAYER, 0));
+        clock.advance(1_000);
+        central.poll();
+        assert_eq!(
+            backend.take_calls(),
+            vec![BackendCall::Load(URL.to_string())]
+        );
+        // logged as the clock started, which has moved 3 seconds since
+        central.handle_command(playback_restart());
+        assert_seeked_to(&backend.take_calls(), 3.0);
+    }
+
+    #[test]
+    fn it_reloads_after_a_crash() {
+        let (mut central, backend, _dir) = new_central();
+
+        central.handle_command(found_url(URL, PLAYER, 30));
+        central.handle_command(playback_restart());
+        backend.take_calls();
+
+        backend.exit(PlayerExit::Crashed);
+        central.handle_command(CentralCommand::PlaybackEvent(PlaybackEvent::Disconnected(
+            1,
+        )));
+        assert_eq!(
+            backend.take_calls(),
+            vec![BackendCall::Load(URL.to_string())]
+        );
+
+        central.handle_command(playback_restart());
+        assert_seeked_to(&backend.take_calls(), 30.0);
+    }
+
+    #[test]
+    fn it_waits_for_a_new_video_after_being_closed() {
+        let (mut central, backend, _dir) = new_central();
+
+        central.handle_command(found_url(URL, PLAYER, 0));
+        central.handle_command(playback_restart());
+        backend.take_calls();
+
+        backend.exit(PlayerExit::Closed);
+        central.handle_command(CentralCommand::PlaybackEvent(PlaybackEvent::Disconnected(
+            1,
+        )));
+        central.handle_command(found_seek(100.0, PLAYER, 0));
+        assert_eq!(backend.take_calls(), vec![]);
+    }
+
+    #[test]
+    fn it_leaves_the_player_alone_while_detached() {
+        let (mut central, backend, _dir) = new_central();
+        let toggle_attach = || {
+            CentralCommand::PlaybackEvent(PlaybackEvent::ClientMessage(vec![
+                "vrc-avpro-sucks".to_string(),
+                "toggle-attach".to_string(),
+            ]))
+        };
+
+        central.handle_command(toggle_attach());
+        assert_eq!(
+            backend.take_calls(),
+            vec![BackendCall::ShowText("Detached from VRChat".to_string())]
+        );
+
+        central.handle_command(found_url(URL, PLAYER, 20));
+        central.handle_command(found_seek(50.0, PLAYER, 5));
+        assert_eq!(backend.take_calls(), vec![]);
+
+        // reattaching catches up with everything that happened meanwhile
+        central.handle_command(toggle_attach());
+        assert_eq!(
+            backend.take_calls(),
+            vec![
+                BackendCall::ShowText("Following VRChat".to_string()),
+                BackendCall::Load(URL.to_string()),
+            ]
+        );
+        central.handle_command(playback_restart());
+        assert_seeked_to(&backend.take_calls(), 55.0);
+    }
+
+    #[test]
+    fn it_applies_the_manual_offset() {
+        let (mut central, backend, _dir) = new_central();
+
+        central.handle_command(found_url(URL, PLAYER, 0));
+        central.handle_command(playback_restart());
+        backend.take_calls();
+
+        central.handle_command(CentralCommand::PlaybackEvent(PlaybackEvent::ClientMessage(
+            vec![
+                "vrc-avpro-sucks".to_string(),
+                "offset".to_string(),
+                "-0.5".to_string(),
+            ],
+        )));
+        let calls = backend.take_calls();
+        assert_eq!(
+            calls[0],
+            BackendCall::ShowText("Offset: -0.50s".to_string())
+        );
+        assert_seeked_to(&calls[1..], -0.5);
+
+        // and keeps applying it to every seek after that
+        central.handle_command(found_seek(100.0, PLAYER, 0));
+        assert_seeked_to(&backend.take_calls(), 99.5);
+    }
+
+    #[test]
+    fn it_resumes_from_saved_state() {
+        let (mut central, backend, dir) = new_central();
+        let log_path = dir.path().join("output_log.txt");
+        std::fs::write(&log_path, "").unwrap();
+
+        let state = PersistedState {
+            log_path,
+            log_offset: 123456,
+            followed_player: Some(PLAYER.to_string()),
+            manual_offset: 0.5,
+            attached: true,
+            now_playing: Some(PersistedNowPlaying {
+                url: URL.to_string(),
+                timestamp: Local::now() - Duration::seconds(10),
+                additional_offset: 40.0,
+            }),
+        };
+        let start_offset =
+            central.resume(state, &LogMatcher::new(builtin_player_definitions(), None));
+        assert_eq!(start_offset, 123456);
+        assert_eq!(
+            backend.take_calls(),
+            vec![BackendCall::Load(URL.to_string())]
+        );
+        central.handle_command(playback_restart());
+        assert_seeked_to(&backend.take_calls(), 50.5);
+
+        // still following the same player as before the restart
+        central.handle_command(found_seek(100.0, PLAYER, 0));
+        assert_seeked_to(&backend.take_calls(), 100.5);
+    }
+
+    #[test]
+    fn it_corrects_drift_reported_by_the_backend() {
+        let (mut central, backend, _dir) = new_central();
+        let position = |position| CentralCommand::PlaybackEvent(PlaybackEvent::Position(position));
+
+        central.handle_command(found_url(URL, PLAYER, 60));
+        // still loading, so there's nothing to correct yet
+        central.handle_command(position(0.0));
+        central.handle_command(playback_restart());
+        backend.take_calls();
+
+        central.handle_command(position(59.0));
+        assert_eq!(backend.take_calls(), vec![]);
+
+        central.handle_command(position(50.0));
+        assert_seeked_to(&backend.take_calls(), 60.0);
+    }
+
+    #[test]
+    fn it_skips_urls_the_policy_denies() {
+        let (mut central, backend, _dir) = new_central_with(SyncSettings {
+            url_policy: UrlPolicy {
+                allow: Vec::new(),
+                deny: vec![Regex::new("example\\.net").unwrap()],
+            },
+            ..settings()
+        });
+
+        central.handle_command(found_url(URL, PLAYER, 0));
+        central.handle_command(playback_restart());
+        backend.take_calls();
+
+        // what we were playing is over, even though we won't play what replaced it
+        central.handle_command(found_url("https://example.net/video.mp4", PLAYER, 0));
+        assert_eq!(backend.take_calls(), vec![BackendCall::Stop]);
+        central.handle_command(found_seek(100.0, PLAYER, 0));
+        assert_eq!(backend.take_calls(), vec![]);
+    }
+
+    #[test]
+    fn it_only_loads_the_last_of_a_burst_of_urls() {
+        let clock = ManualClock::new();
+        let (mut central, backend, _dir) = new_central_with_clock(
+            SyncSettings {
+                url_debounce: std::time::Duration::from_secs(1),
+                ..SyncSettings::default()
+            },
+            Box::new(clock.clone()),
+        );
+        let position = |position| CentralCommand::PlaybackEvent(PlaybackEvent::Position(position));
+
+        central.handle_command(found_url(URL, PLAYER, 30));
+        central.poll();
+        clock.advance(900);
+        central.poll();
+        assert_eq!(backend.take_calls(), vec![]);
+
+        // each new URL starts the wait over
+        central.handle_command(found_url("https://example.net/video.mp4", PLAYER, 10));
+        clock.advance(900);
+        central.poll();
+        assert_eq!(backend.take_calls(), vec![]);
+        // meanwhile, the player still has the old video, so it's left alone
+        central.handle_command(found_seek(100.0, PLAYER, 5));
+        central.handle_command(position(0.0));
+        central.handle_command(playback_restart());
+        assert_eq!(backend.take_calls(), vec![]);
+
+        clock.advance(200);
+        central.poll();
+        assert_eq!(
+            backend.take_calls(),
+            vec![BackendCall::Load(
+                "https://example.net/video.mp4".to_string()
+            )]
+        );
+        // the seek was logged 5 seconds before the clock started, which has moved 2 seconds since
+        central.handle_command(playback_restart());
+        assert_seeked_to(&backend.take_calls(), 107.0);
+    }
+
+    #[test]
+    fn it_recognizes_the_same_youtube_video() {
+        for url in [
+            "https://youtu.be/zL3wWykAKfs",
+            "https://youtu.be/zL3wWykAKfs?t=42",
+            "https://www.youtube.com/watch?v=zL3wWykAKfs",
+            "https://m.youtube.com/watch?feature=share&v=zL3wWykAKfs&t=42s",
+            "http://youtube.com/shorts/zL3wWykAKfs",
+            "https://www.youtube-nocookie.com/embed/zL3wWykAKfs",
+            "https://www.youtube.com/live/zL3wWykAKfs?si=abc",
+        ] {
+            assert_eq!(normalize_url(url), "youtube:zL3wWykAKfs", "{}", url);
+        }
+        // a longer ID is some other video
+        assert_eq!(
+            normalize_url("https://youtu.be/zL3wWykAKfsX"),
+            "https://youtu.be/zL3wWykAKfsX"
+        );
+        assert_eq!(
+            normalize_url("https://example.net/watch?v=zL3wWykAKfs"),
+            "https://example.net/watch?v=zL3wWykAKfs"
+        );
+    }
+
+    #[test]
+    fn it_seeks_instead_of_reloading_the_same_video() {
+        let (mut central, backend, _dir) = new_central();
+
+        central.handle_command(found_url(URL, PLAYER, 30));
+        central.handle_command(playback_restart());
+        backend.take_calls();
+
+        // the ingame player started it over
+        central.handle_command(found_url(
+            "https://www.youtube.com/watch?v=zL3wWykAKfs",
+            PLAYER,
+            2,
+        ));
+        assert_seeked_to(&backend.take_calls(), 2.0);
+
+        // unless asked to start from scratch
+        central.handle_command(CentralCommand::PlaybackEvent(PlaybackEvent::ClientMessage(
+            vec!["vrc-avpro-sucks".to_string(), "reload".to_string()],
+        )));
+        assert_eq!(
+            backend.take_calls(),
+            vec![
+                BackendCall::ShowText("Reloading".to_string()),
+                BackendCall::Load("https://www.youtube.com/watch?v=zL3wWykAKfs".to_string()),
+            ]
+        );
+        // and while it's loading, a re-announcement only moves where it'll be seeked to
+        central.handle_command(found_url(URL, PLAYER, 5));
+        assert_eq!(backend.take_calls(), vec![]);
+        central.handle_command(playback_restart());
+        assert_seeked_to(&backend.take_calls(), 5.0);
+
+        // once the player's been stopped, it doesn't have it anymore
+        central.handle_command(found_url("https://example.net/video.mp4", PLAYER, 0));
+        central.handle_command(joined(WORLD));
+        backend.take_calls();
+        central.handle_command(found_url(URL, PLAYER, 0));
+        assert_eq!(
+            backend.take_calls(),
+            vec![BackendCall::Load(URL.to_string())]
+        );
+    }
+
+    fn duration(duration: f64) -> CentralCommand {
+        CentralCommand::PlaybackEvent(PlaybackEvent::Duration(Some(duration)))
+    }
+
+    #[test]
+    fn it_shows_a_video_thats_already_over_as_ended() {
+        let (mut central, backend, _dir) = new_central();
+
+        // we joined 5 minutes into a 3 minute video
+        central.handle_command(found_url(URL, PLAYER, 300));
+        backend.take_calls();
+        central.handle_command(duration(180.0));
+        central.handle_command(playback_restart());
+        assert_eq!(
+            backend.take_calls(),
+            vec![
+                BackendCall::SetPaused(true),
+                BackendCall::Seek(179.5),
+                BackendCall::ShowText("Ended".to_string()),
+            ]
+        );
+        // it stays that way
+        central.handle_command(found_seek(200.0, PLAYER, 0));
+        assert_eq!(backend.take_calls(), vec![]);
+
+        // until the ingame player seeks back into it
+        central.handle_command(found_seek(60.0, PLAYER, 0));
+        let calls = backend.take_calls();
+        assert_eq!(calls[0], BackendCall::SetPaused(false));
+        assert_seeked_to(&calls[1..], 60.0);
+
+        // and the next video plays as usual
+        central.handle_command(found_seek(500.0, PLAYER, 0));
+        backend.take_calls();
+        central.handle_command(found_url("https://example.net/video.mp4", PLAYER, 0));
+        assert_eq!(
+            backend.take_calls(),
+            vec![
+                BackendCall::Load("https://example.net/video.mp4".to_string()),
+                BackendCall::SetPaused(false),
+            ]
+        );
+        central.handle_command(playback_restart());
+        assert_seeked_to(&backend.take_calls(), 0.0);
+    }
+
+    #[test]
+    fn it_doesnt_seek_past_the_end_to_fix_drift() {
+        let clock = ManualClock::new();
+        let (mut central, backend, _dir) =
+            new_central_with_clock(settings(), Box::new(clock.clone()));
+        let position = |position| CentralCommand::PlaybackEvent(PlaybackEvent::Position(position));
+
+        central.handle_command(found_url(URL, PLAYER, 0));
+        central.handle_command(duration(180.0));
+        central.handle_command(playback_restart());
+        central.handle_command(found_seek(170.0, PLAYER, 0));
+        backend.take_calls();
+
+        // the player stopped at the end, while the video should be 5 seconds past it by now
+        clock.advance(15_000);
+        central.handle_command(position(180.0));
+        assert_eq!(
+            backend.take_calls(),
+            vec![
+                BackendCall::SetPaused(true),
+                BackendCall::Seek(179.5),
+                BackendCall::ShowText("Ended".to_string()),
+            ]
+        );
+        // and that's where it's meant to stay
+        clock.advance(1_000);
+        central.handle_command(position(179.5));
+        assert_eq!(backend.take_calls(), vec![]);
+    }
+
+    #[test]
+    fn it_notices_its_over_once_the_duration_comes_in() {
+        let (mut central, backend, _dir) = new_central();
+
+        central.handle_command(found_url(URL, PLAYER, 300));
+        central.handle_command(playback_restart());
+        assert_seeked_to(&backend.take_calls()[1..], 300.0);
+
+        central.handle_command(duration(180.0));
+        assert_eq!(
+            backend.take_calls(),
+            vec![
+                BackendCall::SetPaused(true),
+                BackendCall::Seek(179.5),
+                BackendCall::ShowText("Ended".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_forgets_about_the_ended_video_in_the_next_world() {
+        let (mut central, backend, _dir) = new_central();
+
+        central.handle_command(found_url(URL, PLAYER, 300));
+        central.handle_command(duration(180.0));
+        central.handle_command(playback_restart());
+        assert!(backend.take_calls().contains(&BackendCall::SetPaused(true)));
+
+        central.handle_command(joined("wrld_elsewhere"));
+        assert_eq!(
+            backend.take_calls(),
+            vec![BackendCall::Stop, BackendCall::SetPaused(false)]
+        );
+
+        // nothing left to unpause
+        central.handle_command(found_url(URL, PLAYER, 30));
+        assert_eq!(
+            backend.take_calls(),
+            vec![BackendCall::Load(URL.to_string())]
+        );
+        central.handle_command(playback_restart());
+        assert_seeked_to(&backend.take_calls(), 30.0);
+    }
+
+    #[test]
+    fn it_recognizes_live_urls() {
+        for url in [
+            "rtmp://live.example.net/app/stream",
+            "rtsp://192.168.1.10:554/stream",
+            "https://www.twitch.tv/somechannel",
+            "https://twitch.tv/somechannel/?referrer=raid",
+            "twitch.tv/some_channel",
+        ] {
+            assert!(is_live_url(url), "{}", url);
+        }
+        for url in [
+            "https://www.twitch.tv/videos/2215324361",
+            "https://clips.twitch.tv/SomeClipName",
+            "https://www.twitch.tv/somechannel/clip/SomeClipName",
+            URL,
+            "https://example.net/stream.m3u8",
+        ] {
+            assert!(!is_live_url(url), "{}", url);
+        }
+    }
+
+    #[test]
+    fn it_loads_the_same_video_again_once_the_player_is_done_with_it() {
+        let (mut central, backend, _dir) = new_central();
+
+        central.handle_command(found_url(URL, PLAYER, 30));
+        central.handle_command(playback_restart());
+        central.handle_command(CentralCommand::PlaybackEvent(PlaybackEvent::FileEnded));
+        backend.take_calls();
+
+        central.handle_command(found_url(URL, PLAYER, 0));
+        assert_eq!(
+            backend.take_calls(),
+            vec![BackendCall::Load(URL.to_string())]
+        );
+    }
+
+    #[test]
+    fn it_never_seeks_live_streams() {
+        let (mut central, backend, _dir) = new_central();
+        const STREAM: &str = "https://www.twitch.tv/somechannel";
+
+        central.handle_command(found_url(STREAM, PLAYER, 30));
+        assert_eq!(
+            backend.take_calls(),
+            vec![
+                BackendCall::LoadLive(STREAM.to_string()),
+                BackendCall::ShowBadge(Some("LIVE".to_string())),
+            ]
+        );
+        central.handle_command(playback_restart());
+        central.handle_command(found_seek(100.0, PLAYER, 0));
+        central.handle_command(CentralCommand::PlaybackEvent(PlaybackEvent::Position(0.0)));
+        assert_eq!(backend.take_calls(), vec![]);
+        assert!(central.status().contains("live"));
+
+        // the next video isn't
+        central.handle_command(found_url(URL, PLAYER, 0));
+        assert_eq!(
+            backend.take_calls(),
+            vec![
+                BackendCall::Load(URL.to_string()),
+                BackendCall::ShowBadge(None),
+            ]
+        );
+        central.handle_command(playback_restart());
+        assert_seeked_to(&backend.take_calls(), 0.0);
+    }
+
+    #[test]
+    fn it_finds_out_from_the_player_whats_live() {
+        let (mut central, backend, _dir) = new_central();
+        const STREAM: &str = "https://example.net/stream.m3u8";
+        let seekable = |seekable| CentralCommand::PlaybackEvent(PlaybackEvent::Seekable(seekable));
+        let partially_seekable = |partially_seekable| {
+            CentralCommand::PlaybackEvent(PlaybackEvent::PartiallySeekable(partially_seekable))
+        };
+
+        central.handle_command(found_url(STREAM, PLAYER, 30));
+        assert_eq!(
+            backend.take_calls(),
+            vec![BackendCall::Load(STREAM.to_string())]
+        );
+        // mpv can seek within what it's cached, but that's all
+        central.handle_command(seekable(Some(true)));
+        assert_eq!(backend.take_calls(), vec![]);
+        central.handle_command(partially_seekable(Some(true)));
+        assert_eq!(
+            backend.take_calls(),
+            vec![
+                BackendCall::GoLive,
+                BackendCall::ShowBadge(Some("LIVE".to_string())),
+            ]
+        );
+        central.handle_command(seekable(Some(false)));
+        central.handle_command(playback_restart());
+        assert_eq!(backend.take_calls(), vec![]);
+
+        // a hard reload doesn't forget
+        central.reload();
+        assert_eq!(
+            backend.take_calls(),
+            vec![
+                BackendCall::ShowText("Reloading".to_string()),
+                BackendCall::LoadLive(STREAM.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_doesnt_take_partially_seekable_videos_for_live() {
+        let (mut central, backend, _dir) = new_central();
+        const VIDEO: &str = "https://example.net/video.m3u8";
+
+        central.handle_command(found_url(VIDEO, PLAYER, 30));
+        assert_eq!(
+            backend.take_calls(),
+            vec![BackendCall::Load(VIDEO.to_string())]
+        );
+        central.handle_command(duration(600.0));
+        central.handle_command(CentralCommand::PlaybackEvent(PlaybackEvent::Seekable(
+            Some(true),
+        )));
+        central.handle_command(CentralCommand::PlaybackEvent(
+            PlaybackEvent::PartiallySeekable(Some(true)),
+        ));
+        assert_eq!(backend.take_calls(), vec![]);
+        central.handle_command(playback_restart());
+        assert_seeked_to(&backend.take_calls(), 30.0);
+        assert!(!central.status().contains("live"));
+    }
+
+    fn joined(world_id: &str) -> CentralCommand {
+        CentralCommand::VrcLogWatcherEvent(VrcLogWatcherEvent::FoundWorldChange(FoundWorldChange {
+            timestamp: Local::now(),
+            world_id: world_id.to_string(),
+        }))
+    }
+
+    fn named(world_name: &str) -> CentralCommand {
+        CentralCommand::VrcLogWatcherEvent(VrcLogWatcherEvent::FoundWorldName(FoundWorldName {
+            timestamp: Local::now(),
+            world_name: world_name.to_string(),
+        }))
+    }
+
+    fn profile(name: &str) -> WorldProfile {
+        WorldProfile {
+            name: name.to_string(),
+            world_id: None,
+            world_name: None,
+            player_name_regex: None,
+            offset: None,
+            disabled: false,
+        }
+    }
+
+    #[test]
+    fn it_applies_the_profile_for_the_world() {
+        let (mut central, backend, _dir) = new_central_with(SyncSettings {
+            profiles: vec![
+                WorldProfile {
+                    world_name: Some(Regex::new("^Movie").unwrap()),
+                    player_name_regex: Some(Regex::new("^Theatre").unwrap()),
+                    offset: Some(-0.5),
+                    ..profile("Movie night")
+                },
+                WorldProfile {
+                    world_id: Some(WORLD.to_string()),
+                    disabled: true,
+                    ..profile("Hangout")
+                },
+            ],
+            ..settings()
+        });
+
+        central.handle_command(joined("wrld_4cf554b4-430c-4f8f-b53e-1f294eed230b"));
+        assert_eq!(backend.take_calls(), vec![BackendCall::Stop]);
+        central.handle_command(named("Movie & Chill"));
+        assert_eq!(
+            backend.take_calls(),
+            vec![BackendCall::ShowText("Profile: Movie night".to_string())]
+        );
+
+        // only the players the profile asks for
+        central.handle_command(found_url(URL, PLAYER, 0));
+        assert_eq!(backend.take_calls(), vec![]);
+        central.handle_command(found_url(URL, "Theatre 1 TVManager", 0));
+        central.handle_command(playback_restart());
+        let calls = backend.take_calls();
+        assert_eq!(calls[0], BackendCall::Load(URL.to_string()));
+        assert_seeked_to(&calls[1..], -0.5);
+
+        // a profile for the world's ID wins, whatever the world is called
+        central.handle_command(joined(WORLD));
+        central.handle_command(named("Movie & Chill"));
+        assert_eq!(
+            backend.take_calls(),
+            vec![
+                BackendCall::Stop,
+                BackendCall::ShowText("Profile: Hangout (not following)".to_string()),
+            ]
+        );
+        central.handle_command(found_url(URL, PLAYER, 0));
+        assert_eq!(backend.take_calls(), vec![]);
+    }
+
+    #[test]
+    fn it_doesnt_bring_the_player_back_in_a_world_it_isnt_following() {
+        let (mut central, backend, _dir) = new_central_with(SyncSettings {
+            profiles: vec![WorldProfile {
+                world_id: Some(WORLD.to_string()),
+                disabled: true,
+                ..profile("Hangout")
+            }],
+            ..settings()
+        });
+
+        central.handle_command(joined(WORLD));
+        central.handle_command(found_url(URL, PLAYER, 0));
+        backend.take_calls();
+
+        backend.exit(PlayerExit::Crashed);
+        central.handle_command(CentralCommand::PlaybackEvent(PlaybackEvent::Disconnected(
+            1,
+        )));
+        assert_eq!(backend.take_calls(), vec![]);
+    }
+
+    #[test]
+    fn it_applies_a_reloaded_config() {
+        let (mut central, backend, _dir) = new_central();
+        central.handle_command(joined(WORLD));
+        central.handle_command(found_url(URL, PLAYER, 0));
+        central.handle_command(playback_restart());
+        backend.take_calls();
+
+        // a new offset takes over, and the player is put back where it should be with it
+        central.handle_command(CentralCommand::ConfigReloaded(SyncSettings {
+            offset: 1.5,
+            ..settings()
+        }));
+        let calls = backend.take_calls();
+        assert_eq!(
+            calls[0],
+            BackendCall::ShowText("Offset: +1.50s".to_string())
+        );
+        assert_seeked_to(&calls[1..], 1.5);
+
+        // so does a new profile for the world we're in
+        central.handle_command(CentralCommand::ConfigReloaded(SyncSettings {
+            offset: 1.5,
+            profiles: vec![WorldProfile {
+                world_id: Some(WORLD.to_string()),
+                offset: Some(-1.0),
+                ..profile("Hangout")
+            }],
+            ..settings()
+        }));
+        let calls = backend.take_calls();
+        assert_eq!(
+            calls[0],
+            BackendCall::ShowText("Profile: Hangout".to_string())
+        );
+        assert_seeked_to(&calls[1..], -1.0);
+
+        // nothing changed, so nothing happens
+        central.handle_command(CentralCommand::ConfigReloaded(SyncSettings {
+            offset: 1.5,
+            profiles: vec![WorldProfile {
+                world_id: Some(WORLD.to_string()),
+                offset: Some(-1.0),
+                ..profile("Hangout")
+            }],
+            ..settings()
+        }));
+        assert_eq!(backend.take_calls(), vec![]);
+
+        // and what's playing is stopped, if it's not allowed anymore
+        central.handle_command(CentralCommand::ConfigReloaded(SyncSettings {
+            url_policy: UrlPolicy {
+                allow: Vec::new(),
+                deny: vec![Regex::new("youtu").unwrap()],
+            },
+            ..settings()
+        }));
+        assert_eq!(
+            backend.take_calls(),
+            vec![
+                BackendCall::ShowText("No profile".to_string()),
+                BackendCall::Stop
+            ]
+        );
+    }
+
+    #[test]
+    fn it_saves_and_closes_the_player_on_shutdown() {
+        let (mut central, backend, dir) = new_central();
+        central.handle_command(found_url(URL, PLAYER, 0));
+        central.handle_command(CentralCommand::VrcLogWatcherCheckpoint(1234));
+        backend.take_calls();
+
+        central.handle_command(CentralCommand::Shutdown);
+        assert_eq!(backend.take_calls(), vec![BackendCall::Shutdown]);
+        let state = load_state(&dir.path().join("state.json")).unwrap();
+        assert_eq!(state.log_offset, 1234);
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+
+// Where Central gets the current time from, for working out where a video should be by now.
+pub(crate) trait Clock {
+    fn now(&self) -> DateTime<Local>;
+
+    // When, by the event loop's reckoning, this clock will read `at`. For timers that go by this clock.
+    fn instant_at(&self, at: DateTime<Local>) -> Instant;
+}
+
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+
+    fn instant_at(&self, at: DateTime<Local>) -> Instant {
+        let until = at.signed_duration_since(Local::now());
+        Instant::now() + until.to_std().unwrap_or(Duration::ZERO)
+    }
+}
+
+// Maps between the time in a log being replayed and the actual time. The replay starts at the log's first timestamp, and
+// the log's time passes `speed` times as fast as ours.
+#[derive(Clone, Copy)]
+pub(crate) struct ReplayTimeline {
+    log_start: DateTime<Local>,
+    wall_start: Instant,
+    speed: f64,
+}
+
+impl ReplayTimeline {
+    pub(crate) fn start(log_start: DateTime<Local>, speed: f64) -> Self {
+        Self {
+            log_start,
+            wall_start: Instant::now(),
+            speed,
+        }
+    }
+
+    // When a line logged at this time should be replayed.
+    pub(crate) fn wall_time_for(&self, log_time: DateTime<Local>) -> Instant {
+        let log_elapsed = log_time.signed_duration_since(self.log_start);
+        let log_elapsed = log_elapsed.to_std().unwrap_or(Duration::ZERO);
+        self.wall_start + log_elapsed.div_f64(self.speed)
+    }
+}
+
+// What time it is in the log being replayed.
+pub(crate) struct ReplayClock(pub(crate) ReplayTimeline);
+
+impl Clock for ReplayClock {
+    fn now(&self) -> DateTime<Local> {
+        let timeline = &self.0;
+        let log_elapsed = timeline.wall_start.elapsed().mul_f64(timeline.speed);
+        timeline.log_start + chrono::Duration::from_std(log_elapsed).unwrap_or_default()
+    }
+
+    fn instant_at(&self, at: DateTime<Local>) -> Instant {
+        self.0.wall_time_for(at)
+    }
+}
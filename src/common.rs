@@ -1,20 +1,36 @@
+// See logging.rs for where these end up.
+
+#[macro_export]
+macro_rules! log_trace {
+    ($($arg:tt)*) => {
+        $crate::logging::log($crate::logging::Level::Trace, module_path!(), file!(), line!(), format_args!($($arg)*))
+    }
+}
+
 #[macro_export]
 macro_rules! log_debug {
     ($($arg:tt)*) => {
-        eprintln!("[DEBUG][{}:{}] {}", file!(), line!(), format_args!($($arg)*));
+        $crate::logging::log($crate::logging::Level::Debug, module_path!(), file!(), line!(), format_args!($($arg)*))
+    }
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        $crate::logging::log($crate::logging::Level::Info, module_path!(), file!(), line!(), format_args!($($arg)*))
     }
 }
 
 #[macro_export]
 macro_rules! log_error {
     ($($arg:tt)*) => {
-        eprintln!("[ERROR][{}:{}] {}", file!(), line!(), format_args!($($arg)*));
+        $crate::logging::log($crate::logging::Level::Error, module_path!(), file!(), line!(), format_args!($($arg)*))
     }
 }
 
 #[macro_export]
 macro_rules! log_warn {
     ($($arg:tt)*) => {
-        eprintln!("[WARN][{}:{}] {}", file!(), line!(), format_args!($($arg)*));
+        $crate::logging::log($crate::logging::Level::Warn, module_path!(), file!(), line!(), format_args!($($arg)*))
     }
 }
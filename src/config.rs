@@ -0,0 +1,509 @@
+use std::{
+    collections::HashSet,
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::{mpsc::Receiver, Arc},
+    time::{Duration, Instant},
+};
+
+use mio::Waker;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    central::{SyncSettings, UrlPolicy, WorldProfile},
+    event_loop::loop_channel,
+    log_error, log_warn,
+    logging::{LogFilter, LogFormat, LOG_FILTER_ENV_VAR},
+    vrc_log_reader::{builtin_player_definitions, default_vrc_log_dir, PlayerDefinition},
+    xdg,
+};
+
+// Where to read the config from, instead of config.toml in the config dir.
+pub(crate) const CONFIG_PATH_ENV_VAR: &str = "VRC_AVPRO_SUCKS_CONFIG";
+
+// Environment variables that override a single setting each, on top of the config file.
+const PLAYER_ENV_VAR: &str = "VRC_AVPRO_SUCKS_PLAYER";
+const VRCHAT_LOG_DIR_ENV_VAR: &str = "VRC_AVPRO_SUCKS_VRCHAT_LOG_DIR";
+const MPV_PATH_ENV_VAR: &str = "VRC_AVPRO_SUCKS_MPV_PATH";
+
+// Saving a file can take several writes, and halfway through, it may well be empty. So after a change, wait until
+// it's been quiet for this long before reading it.
+const RELOAD_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Player {
+    Mpv,
+    Vlc,
+}
+
+// Everything config.toml can set. Anything left out of the file keeps its default. Environment variables go on top of
+// that, and then the command line.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    // Which video player to drive.
+    pub(crate) player: Player,
+    // Where VRChat writes its logs. We follow the newest one in here.
+    pub(crate) vrchat_log_dir: PathBuf,
+    // Only follow ingame players whose names match this.
+    pub(crate) player_name_regex: Option<String>,
+    // Where the player we start should listen, instead of a fresh one in $XDG_RUNTIME_DIR.
+    pub(crate) socket_path: Option<PathBuf>,
+    // Where we listen for commands, instead of control.sock in $XDG_RUNTIME_DIR.
+    pub(crate) control_socket: Option<PathBuf>,
+    // Ingame players to recognize, besides the built-in ones. See PlayersFile.
+    pub(crate) players_file: PathBuf,
+    // Whether to close the player we started when we exit. One we attached to is left alone either way.
+    pub(crate) quit_player_on_exit: bool,
+    pub(crate) sync: SyncConfig,
+    pub(crate) urls: UrlConfig,
+    pub(crate) mpv: MpvConfig,
+    pub(crate) vlc: VlcConfig,
+    pub(crate) logging: LoggingConfig,
+    // Per-world settings, as [[profiles]].
+    pub(crate) profiles: Vec<ProfileConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct SyncConfig {
+    // What the manual offset starts out as, if there's no saved state to take it from.
+    pub(crate) offset: f64,
+    // How far off the player's reported position can be before we seek it back into place.
+    pub(crate) max_drift: f64,
+    // In seconds, how long a new URL has to stay Now Playing before we load it, so that clicking through a playlist
+    // only loads wherever it stops. 0 loads every one right away.
+    pub(crate) url_debounce: f64,
+}
+
+// Regexes, matched against the whole URL. A URL is only loaded if it matches none of `deny`, and, unless `allow` is
+// empty, at least one of `allow`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct UrlConfig {
+    pub(crate) allow: Vec<String>,
+    pub(crate) deny: Vec<String>,
+}
+
+// Applies while we're in the world with this ID, or, failing that, one whose name matches the `world_name` regex.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ProfileConfig {
+    // Shown on the OSD when it kicks in.
+    pub(crate) name: String,
+    pub(crate) world_id: Option<String>,
+    pub(crate) world_name: Option<String>,
+    // Which of the world's ingame players to follow.
+    pub(crate) player_name_regex: Option<String>,
+    // What the manual offset starts out as in this world, instead of sync.offset.
+    pub(crate) offset: Option<f64>,
+    // Leave the player alone in this world.
+    #[serde(default)]
+    pub(crate) disabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct MpvConfig {
+    pub(crate) path: PathBuf,
+    // Passed after our own arguments.
+    pub(crate) args: Vec<String>,
+    // Where mpv's stderr goes.
+    pub(crate) log_file: Option<PathBuf>,
+    // Instead of the built-in seconds.lua.
+    pub(crate) script: Option<PathBuf>,
+    // The IPC socket of an mpv that's already running. If set, we use that one instead of starting our own.
+    pub(crate) attach_socket: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct VlcConfig {
+    pub(crate) path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LoggingConfig {
+    // A filter for what goes to the terminal, like `info,mpv_commander=trace`.
+    pub(crate) level: String,
+    pub(crate) format: LogFormat,
+    // Whether to also keep a log file, and where.
+    pub(crate) file: bool,
+    pub(crate) dir: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            player: Player::Mpv,
+            vrchat_log_dir: default_vrc_log_dir(),
+            player_name_regex: None,
+            socket_path: None,
+            control_socket: None,
+            players_file: xdg::config_dir().join("players.toml"),
+            quit_player_on_exit: true,
+            sync: SyncConfig::default(),
+            urls: UrlConfig::default(),
+            mpv: MpvConfig::default(),
+            vlc: VlcConfig::default(),
+            logging: LoggingConfig::default(),
+            profiles: Vec::new(),
+        }
+    }
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        let defaults = SyncSettings::default();
+        Self {
+            offset: defaults.offset,
+            max_drift: defaults.max_drift,
+            url_debounce: defaults.url_debounce.as_secs_f64(),
+        }
+    }
+}
+
+impl Default for MpvConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("mpv"),
+            args: Vec::new(),
+            log_file: None,
+            script: None,
+            attach_socket: None,
+        }
+    }
+}
+
+impl Default for VlcConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("vlc"),
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+            file: true,
+            dir: xdg::state_dir(),
+        }
+    }
+}
+
+pub(crate) fn default_config_path() -> PathBuf {
+    match env::var_os(CONFIG_PATH_ENV_VAR) {
+        Some(path) => PathBuf::from(path),
+        None => xdg::config_dir().join("config.toml"),
+    }
+}
+
+impl Config {
+    // A missing file is fine, and just means the defaults. A broken one isn't.
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents).map_err(|e| format!("{:#?}: {}", path, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Failed to read {:#?}: {}", path, e)),
+        }
+    }
+
+    pub(crate) fn parse(contents: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(contents).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // `var` is std::env::var, except in tests.
+    pub(crate) fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        if let Some(player) = var(PLAYER_ENV_VAR) {
+            self.player = match player.as_str() {
+                "mpv" => Player::Mpv,
+                "vlc" => Player::Vlc,
+                _ => return Err(format!("{}: unknown player {:?}", PLAYER_ENV_VAR, player)),
+            };
+        }
+        if let Some(dir) = var(VRCHAT_LOG_DIR_ENV_VAR) {
+            self.vrchat_log_dir = PathBuf::from(dir);
+        }
+        if let Some(path) = var(MPV_PATH_ENV_VAR) {
+            self.mpv.path = PathBuf::from(path);
+        }
+        if let Some(level) = var(LOG_FILTER_ENV_VAR) {
+            self.logging.level = level;
+        }
+        self.validate()
+    }
+
+    // Everything that's checked here can be unwrapped afterwards.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        self.player_name_regex()?;
+        self.log_filter()?;
+        self.sync_settings()?;
+        Ok(())
+    }
+
+    pub(crate) fn player_name_regex(&self) -> Result<Option<Regex>, String> {
+        self.player_name_regex
+            .as_deref()
+            .map(|regex| Regex::new(regex).map_err(|e| format!("player_name_regex: {}", e)))
+            .transpose()
+    }
+
+    pub(crate) fn log_filter(&self) -> Result<LogFilter, String> {
+        self.logging
+            .level
+            .parse()
+            .map_err(|e| format!("logging.level: {}", e))
+    }
+
+    pub(crate) fn log_file_path(&self) -> Option<PathBuf> {
+        self.logging
+            .file
+            .then(|| self.logging.dir.join("vrc-avpro-sucks.log"))
+    }
+
+    pub(crate) fn sync_settings(&self) -> Result<SyncSettings, String> {
+        if self.sync.max_drift.is_nan() || self.sync.max_drift <= 0.0 {
+            return Err(format!(
+                "sync.max_drift: must be more than 0, not {}",
+                self.sync.max_drift
+            ));
+        }
+        if !self.sync.url_debounce.is_finite() || self.sync.url_debounce < 0.0 {
+            return Err(format!(
+                "sync.url_debounce: must be 0 or more, not {}",
+                self.sync.url_debounce
+            ));
+        }
+        let compile = |name: &str, regexes: &[String]| {
+            regexes
+                .iter()
+                .map(|regex| Regex::new(regex).map_err(|e| format!("urls.{}: {}", name, e)))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(SyncSettings {
+            offset: self.sync.offset,
+            max_drift: self.sync.max_drift,
+            url_debounce: Duration::from_secs_f64(self.sync.url_debounce),
+            url_policy: UrlPolicy {
+                allow: compile("allow", &self.urls.allow)?,
+                deny: compile("deny", &self.urls.deny)?,
+            },
+            profiles: self
+                .profiles
+                .iter()
+                .map(ProfileConfig::compile)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    // For --print-config.
+    pub(crate) fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("Failed to serialize the config.")
+    }
+
+    // The settings that don't take effect until we're restarted, if any of them differ between the two.
+    pub(crate) fn needs_restart_for(&self, other: &Config) -> bool {
+        let only_live_changes = Config {
+            player_name_regex: self.player_name_regex.clone(),
+            sync: self.sync.clone(),
+            urls: self.urls.clone(),
+            profiles: self.profiles.clone(),
+            logging: LoggingConfig {
+                level: self.logging.level.clone(),
+                ..other.logging.clone()
+            },
+            ..other.clone()
+        };
+        only_live_changes != *self
+    }
+}
+
+// players.toml, which describes ingame players that aren't built in, as [[players]]. One with the same name as a
+// built-in one replaces it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct PlayersFile {
+    pub(crate) players: Vec<PlayerDefinitionConfig>,
+}
+
+// See PlayerDefinition for what each of these is for.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct PlayerDefinitionConfig {
+    pub(crate) name: String,
+    pub(crate) needle: String,
+    pub(crate) url_regex: String,
+    pub(crate) seek_regex: String,
+}
+
+impl PlayersFile {
+    // Like the config, a missing file is fine, and just means there's nothing besides the built-in players.
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents).map_err(|e| format!("{:#?}: {}", path, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Failed to read {:#?}: {}", path, e)),
+        }
+    }
+
+    pub(crate) fn parse(contents: &str) -> Result<Self, String> {
+        let players_file: Self = toml::from_str(contents).map_err(|e| e.to_string())?;
+        players_file.player_definitions()?;
+        Ok(players_file)
+    }
+
+    // The built-in players, plus or replaced by ours.
+    pub(crate) fn player_definitions(&self) -> Result<Vec<PlayerDefinition>, String> {
+        let mut definitions = builtin_player_definitions();
+        for player in &self.players {
+            let definition = player.compile()?;
+            match definitions.iter_mut().find(|d| d.name == definition.name) {
+                Some(builtin) => *builtin = definition,
+                None => definitions.push(definition),
+            }
+        }
+        Ok(definitions)
+    }
+}
+
+impl PlayerDefinitionConfig {
+    fn compile(&self) -> Result<PlayerDefinition, String> {
+        if self.needle.is_empty() {
+            return Err(format!("player {:?}: needle can't be empty", self.name));
+        }
+        let compile = |field: &str, regex: &str, captures: &[&str]| {
+            let regex = Regex::new(regex)
+                .map_err(|e| format!("player {:?}: {}: {}", self.name, field, e))?;
+            for capture in captures {
+                if !regex.capture_names().flatten().any(|name| name == *capture) {
+                    return Err(format!(
+                        "player {:?}: {}: must capture {:?}",
+                        self.name, field, capture
+                    ));
+                }
+            }
+            Ok(regex)
+        };
+        Ok(PlayerDefinition {
+            name: self.name.clone(),
+            needle: self.needle.clone(),
+            url_regex: compile(
+                "url_regex",
+                &self.url_regex,
+                &["timestamp", "player_name", "url"],
+            )?,
+            seek_regex: compile(
+                "seek_regex",
+                &self.seek_regex,
+                &["timestamp", "player_name", "new_offset"],
+            )?,
+        })
+    }
+}
+
+impl ProfileConfig {
+    fn compile(&self) -> Result<WorldProfile, String> {
+        if self.world_id.is_none() && self.world_name.is_none() {
+            return Err(format!(
+                "profile {:?}: needs a world_id or a world_name",
+                self.name
+            ));
+        }
+        let compile = |field: &str, regex: &Option<String>| {
+            regex
+                .as_deref()
+                .map(|regex| {
+                    Regex::new(regex)
+                        .map_err(|e| format!("profile {:?}: {}: {}", self.name, field, e))
+                })
+                .transpose()
+        };
+        Ok(WorldProfile {
+            name: self.name.clone(),
+            world_id: self.world_id.clone(),
+            world_name: compile("world_name", &self.world_name)?,
+            player_name_regex: compile("player_name_regex", &self.player_name_regex)?,
+            offset: self.offset,
+            disabled: self.disabled,
+        })
+    }
+}
+
+// Notices whenever any of `paths` might have changed, and once things have settled down, says it's time to reload.
+// Like VrcLogWatcher, this watches the directories they're in, since editors tend to save by writing a new file and
+// renaming it over the old one.
+pub(crate) struct FileWatcher {
+    paths: Vec<PathBuf>,
+    // Has to be kept around, or it stops watching.
+    _watcher: RecommendedWatcher,
+    changes: Receiver<notify::Result<notify::Event>>,
+    // When it'll have been quiet for long enough since the last change.
+    reload_at: Option<Instant>,
+}
+
+impl FileWatcher {
+    pub(crate) fn new(paths: Vec<PathBuf>, waker: Arc<Waker>) -> notify::Result<Self> {
+        let (tx, changes) = loop_channel(waker);
+        let mut watcher = RecommendedWatcher::new(
+            move |res| {
+                // If the event loop is gone, we're shutting down anyway.
+                let _ = tx.send(res);
+            },
+            notify::Config::default(),
+        )?;
+        let watch_dirs: HashSet<&Path> = paths
+            .iter()
+            .map(|path| path.parent().unwrap_or(Path::new(".")))
+            .collect();
+        for watch_dir in watch_dirs {
+            if let Err(e) = watcher.watch(watch_dir, RecursiveMode::NonRecursive) {
+                // Most likely, it doesn't exist, so neither does anything in it. But if it's created later, on purpose, it's
+                // worth knowing why what's put there isn't picked up.
+                log_warn!(
+                    "Not watching {:#?} for changes, so a config saved there needs a restart to take effect: {}",
+                    watch_dir,
+                    e
+                );
+            }
+        }
+        Ok(Self {
+            paths,
+            _watcher: watcher,
+            changes,
+            reload_at: None,
+        })
+    }
+
+    // Whether it's time to reload.
+    pub(crate) fn poll(&mut self, now: Instant) -> bool {
+        for res in self.changes.try_iter() {
+            match res {
+                // otherwise, it's some other file in the same directory
+                Ok(event) if event.paths.iter().any(|path| self.paths.contains(path)) => {
+                    // whatever else happens meanwhile is part of the same save
+                    self.reload_at = Some(now + RELOAD_DELAY);
+                }
+                Ok(_) => {}
+                Err(e) => log_error!("Error watching the config: {:?}", e),
+            }
+        }
+        if self.reload_at.is_some_and(|reload_at| now >= reload_at) {
+            self.reload_at = None;
+            return true;
+        }
+        false
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.reload_at
+    }
+}
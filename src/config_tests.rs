@@ -0,0 +1,150 @@
+#[cfg(test)]
+mod config {
+    use std::collections::HashMap;
+
+    use crate::{
+        config::{Config, Player, PlayersFile, UrlConfig},
+        logging::LogFormat,
+    };
+
+    #[test]
+    fn it_keeps_defaults_for_whatever_is_left_out() {
+        let config = Config::parse(
+            r#"
+            player = "vlc"
+
+            [sync]
+            max_drift = 0.5
+
+            [mpv]
+            args = ["--profile=vrc"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.player, Player::Vlc);
+        assert_eq!(config.sync.max_drift, 0.5);
+        assert_eq!(config.sync.offset, 0.0);
+        assert_eq!(config.mpv.args, vec!["--profile=vrc".to_string()]);
+        assert_eq!(config.mpv.path, Config::default().mpv.path);
+        assert_eq!(config.logging, Config::default().logging);
+    }
+
+    #[test]
+    fn it_rejects_mistakes() {
+        // typos shouldn't be silently ignored
+        assert!(Config::parse("max_drfit = 1.0").is_err());
+        assert!(Config::parse("player_name_regex = \"(\"").is_err());
+        assert!(Config::parse("[urls]\ndeny = [\"[\"]").is_err());
+        assert!(Config::parse("[sync]\nmax_drift = 0.0").is_err());
+        assert!(Config::parse("[sync]\nurl_debounce = -1.0").is_err());
+        assert!(Config::parse("[logging]\nlevel = \"loud\"").is_err());
+        // a profile for no world in particular
+        assert!(Config::parse("[[profiles]]\nname = \"Everywhere\"\noffset = 1.0").is_err());
+    }
+
+    #[test]
+    fn it_lets_the_environment_override_the_file() {
+        let mut config = Config::parse("player = \"vlc\"\n[logging]\nformat = \"json\"").unwrap();
+        let env = HashMap::from([
+            ("VRC_AVPRO_SUCKS_PLAYER", "mpv"),
+            ("VRC_AVPRO_SUCKS_LOG", "debug,mpv_commander=trace"),
+        ]);
+        config
+            .apply_env(|name| env.get(name).map(|value| value.to_string()))
+            .unwrap();
+        assert_eq!(config.player, Player::Mpv);
+        assert_eq!(config.logging.level, "debug,mpv_commander=trace");
+        assert_eq!(config.logging.format, LogFormat::Json);
+
+        let env = HashMap::from([("VRC_AVPRO_SUCKS_PLAYER", "totem")]);
+        assert!(config
+            .apply_env(|name| env.get(name).map(|value| value.to_string()))
+            .is_err());
+    }
+
+    #[test]
+    fn it_prints_what_it_can_read_back() {
+        let config = Config {
+            player_name_regex: Some("^ProTV".to_string()),
+            urls: UrlConfig {
+                allow: Vec::new(),
+                deny: vec![r"^https?://(www\.)?twitch\.tv/".to_string()],
+            },
+            ..Config::default()
+        };
+        assert_eq!(Config::parse(&config.to_toml()), Ok(config));
+    }
+
+    #[test]
+    fn it_compiles_the_url_policy() {
+        let config = Config::parse(
+            r#"
+            [urls]
+            allow = ['^https://(www\.)?youtube\.com/', '^https://youtu\.be/']
+            deny = ['list=']
+            "#,
+        )
+        .unwrap();
+        let policy = config.sync_settings().unwrap().url_policy;
+        assert!(policy.allows("https://youtu.be/zL3wWykAKfs"));
+        assert!(!policy.allows("https://youtu.be/zL3wWykAKfs?list=PL123"));
+        assert!(!policy.allows("https://example.net/video.mp4"));
+    }
+
+    #[test]
+    fn it_adds_players_from_the_players_file() {
+        let players_file = PlayersFile::parse(
+            r#"
+            [[players]]
+            name = "USharpVideo"
+            needle = "[USharpVideo]"
+            url_regex = '^(?P<timestamp>[0-9.: ]+) .*\[USharpVideo\] \((?P<player_name>.*)\) Started video: (?P<url>\S+)'
+            seek_regex = '^(?P<timestamp>[0-9.: ]+) .*\[USharpVideo\] \((?P<player_name>.*)\) Seeked to (?P<new_offset>[0-9.]+)'
+            "#,
+        )
+        .unwrap();
+        let names: Vec<String> = players_file
+            .player_definitions()
+            .unwrap()
+            .into_iter()
+            .map(|definition| definition.name)
+            .collect();
+        assert_eq!(names, vec!["ProTV", "USharpVideo"]);
+        assert_eq!(PlayersFile::parse("").unwrap(), PlayersFile::default());
+    }
+
+    #[test]
+    fn it_rejects_players_that_cant_be_followed() {
+        // no URL to load
+        assert!(PlayersFile::parse(
+            r#"
+            [[players]]
+            name = "ProTV"
+            needle = "TVManager"
+            url_regex = '^(?P<timestamp>[0-9.: ]+) .*TVManager \((?P<player_name>.*)\)\] Now Playing'
+            seek_regex = '^(?P<timestamp>[0-9.: ]+) .*TVManager \((?P<player_name>.*)\)\] Updating to (?P<new_offset>[0-9.]+)'
+            "#,
+        )
+        .is_err());
+        assert!(PlayersFile::parse("[[players]]\nname = \"ProTV\"").is_err());
+    }
+
+    #[test]
+    fn it_knows_what_needs_a_restart() {
+        let config = Config::default();
+        let live = Config {
+            player_name_regex: Some("^ProTV".to_string()),
+            urls: UrlConfig {
+                allow: Vec::new(),
+                deny: vec!["list=".to_string()],
+            },
+            ..Config::default()
+        };
+        assert!(!config.needs_restart_for(&live));
+        let restart = Config {
+            player: Player::Vlc,
+            ..Config::default()
+        };
+        assert!(config.needs_restart_for(&restart));
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    io,
+    os::unix::net::UnixStream as StdUnixStream,
+    path::{Path, PathBuf},
+};
+
+use mio::{net::UnixListener, Interest, Registry, Token};
+
+use crate::{
+    event_loop::{LineConnection, CONTROL_LISTENER, FIRST_CONTROL_CLIENT},
+    log_debug, log_warn,
+    playback_backend::remove_socket,
+    xdg,
+};
+
+// What can be asked of us over the control socket, one per line:
+//
+//   status          what's playing, and how we're following it
+//   offset <delta>  shifts the sync offset, like Alt+[ and Alt+] in mpv, e.g. `offset -0.1`
+//   toggle-attach   detaches from or reattaches to VRChat, like Alt+d
+//   reload          loads what's playing from scratch, like Alt+r
+//   quit            shuts down, like SIGTERM
+//
+// Each one is answered with a line starting with `ok` or `error`.
+#[derive(Debug, PartialEq)]
+pub(crate) enum ControlCommand {
+    Status,
+    Offset(f64),
+    ToggleAttach,
+    Reload,
+    Quit,
+}
+
+pub(crate) fn parse_control_command(line: &str) -> Result<ControlCommand, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["status"] => Ok(ControlCommand::Status),
+        ["offset", delta] => match delta.parse::<f64>() {
+            Ok(delta) if delta.is_finite() => Ok(ControlCommand::Offset(delta)),
+            _ => Err(format!("invalid offset {:?}", delta)),
+        },
+        ["toggle-attach"] => Ok(ControlCommand::ToggleAttach),
+        ["reload"] => Ok(ControlCommand::Reload),
+        ["quit"] => Ok(ControlCommand::Quit),
+        _ => Err(format!("unknown command {:?}", line.trim())),
+    }
+}
+
+// Where the control socket goes, unless the config says otherwise. Unlike the player's socket, this one's the same every
+// run, so that scripts can find it.
+pub(crate) fn default_control_socket_path() -> io::Result<PathBuf> {
+    let runtime_dir = xdg::runtime_dir();
+    xdg::create_private_dir(&runtime_dir)?;
+    Ok(runtime_dir.join("control.sock"))
+}
+
+// Listens on the control socket, and keeps track of whoever's connected to it.
+pub(crate) struct ControlServer {
+    socket_path: PathBuf,
+    listener: UnixListener,
+    registry: Registry,
+    clients: Vec<LineConnection>,
+    next_token: usize,
+}
+
+impl ControlServer {
+    pub(crate) fn bind(socket_path: &Path, registry: &Registry) -> io::Result<Self> {
+        // Whatever's left at this path belongs to an instance that's gone, unless it's still answering.
+        if socket_path.exists() {
+            if StdUnixStream::connect(socket_path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "another instance is already listening there",
+                ));
+            }
+            remove_socket(socket_path);
+        }
+        let mut listener = UnixListener::bind(socket_path)?;
+        registry.register(&mut listener, CONTROL_LISTENER, Interest::READABLE)?;
+        Ok(Self {
+            socket_path: socket_path.to_path_buf(),
+            listener,
+            registry: registry.try_clone()?,
+            clients: Vec::new(),
+            next_token: FIRST_CONTROL_CLIENT.0,
+        })
+    }
+
+    // Takes on whoever's connected since the last call, and returns what all of them have asked for since, each along
+    // with who to reply to.
+    pub(crate) fn poll(&mut self) -> Vec<(Token, Result<ControlCommand, String>)> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    let token = Token(self.next_token);
+                    self.next_token += 1;
+                    match LineConnection::register(stream, &self.registry, token) {
+                        Ok(client) => {
+                            log_debug!("Control client {} connected.", token.0);
+                            self.clients.push(client);
+                        }
+                        Err(e) => log_warn!("Failed to take on a control client: {}", e),
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log_warn!("Failed to accept a control client: {}", e);
+                    break;
+                }
+            }
+        }
+
+        let mut commands = Vec::new();
+        for client in &mut self.clients {
+            match client.read_lines() {
+                Ok(lines) => commands.extend(
+                    lines
+                        .iter()
+                        .filter(|line| !line.trim().is_empty())
+                        .map(|line| (client.token(), parse_control_command(line))),
+                ),
+                Err(e) => log_debug!("Lost control client {}: {}", client.token().0, e),
+            }
+        }
+        commands
+    }
+
+    // Queues up a reply. It's sent on the next flush.
+    pub(crate) fn reply(&mut self, client: Token, reply: &str) {
+        if let Some(client) = self.clients.iter_mut().find(|c| c.token() == client) {
+            client.queue(&format!("{}\n", reply));
+        }
+    }
+
+    // Sends replies, and hangs up on whoever's done.
+    pub(crate) fn flush(&mut self) {
+        let mut clients = Vec::with_capacity(self.clients.len());
+        for mut client in self.clients.drain(..) {
+            let result = client.flush();
+            if let Err(e) = &result {
+                log_debug!("Lost control client {}: {}", client.token().0, e);
+            }
+            if result.is_err() || client.is_closed() {
+                log_debug!("Control client {} disconnected.", client.token().0);
+                client.close(&self.registry);
+            } else {
+                clients.push(client);
+            }
+        }
+        self.clients = clients;
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        remove_socket(&self.socket_path);
+    }
+}
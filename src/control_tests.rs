@@ -0,0 +1,29 @@
+#[cfg(test)]
+mod control {
+    use crate::control::{parse_control_command, ControlCommand};
+
+    #[test]
+    fn it_parses_commands() {
+        assert_eq!(parse_control_command("status"), Ok(ControlCommand::Status));
+        assert_eq!(
+            parse_control_command("  offset -0.25 \r"),
+            Ok(ControlCommand::Offset(-0.25))
+        );
+        assert_eq!(
+            parse_control_command("toggle-attach"),
+            Ok(ControlCommand::ToggleAttach)
+        );
+        assert_eq!(parse_control_command("reload"), Ok(ControlCommand::Reload));
+        assert_eq!(parse_control_command("quit"), Ok(ControlCommand::Quit));
+    }
+
+    #[test]
+    fn it_rejects_nonsense() {
+        assert!(parse_control_command("").is_err());
+        assert!(parse_control_command("offset").is_err());
+        assert!(parse_control_command("offset soon").is_err());
+        assert!(parse_control_command("offset NaN").is_err());
+        assert!(parse_control_command("status please").is_err());
+        assert!(parse_control_command("play").is_err());
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    io,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Receiver,
+        Arc,
+    },
+    time::Instant,
+};
+
+use mio::Interest;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook_mio::v1_0::Signals;
+
+use crate::{
+    central::{Central, CentralCommand, SyncSettings},
+    config::FileWatcher,
+    control::{ControlCommand, ControlServer},
+    event_loop::{earliest, loop_channel, EventLoop, LoopSender, SIGNALS},
+    log_error, log_info,
+    replay::Replay,
+    vrc_log_reader::{LogMatcher, VrcLogWatcher, VrcLogWatcherEvent},
+};
+
+// What other threads can ask of the daemon.
+pub(crate) enum DaemonCommand {
+    // From a thread that panicked, or a test.
+    Shutdown,
+    // What the config says now, for the log watcher and Central.
+    Reload(Box<(SyncSettings, LogMatcher)>),
+}
+
+// Where the lines Central hears about come from.
+pub(crate) enum LogSource {
+    // VRChat's log, as it's written.
+    Watcher(Box<VrcLogWatcher>),
+    // A recorded log, played back.
+    Replay(Replay),
+}
+
+// Reads the config again, once it's changed. Returns what to apply, or None if there's nothing new, or the change broke
+// something.
+pub(crate) type Reloader = Box<dyn FnMut() -> Option<(SyncSettings, LogMatcher)>>;
+
+// Central, along with everything that feeds it: the log, the config file, signals and the control socket. It all runs on
+// the main thread, taking turns on a single event loop, so once Central has shut down, there's nothing left running to
+// stop.
+pub(crate) struct Daemon {
+    event_loop: EventLoop,
+    central: Central,
+    log_source: LogSource,
+    inbox: Receiver<DaemonCommand>,
+    inbox_tx: LoopSender<DaemonCommand>,
+    signals: Option<Signals>,
+    // Set on the first signal, so that a second one exits right away, even if shutting down gets stuck.
+    shutting_down: Arc<AtomicBool>,
+    config_watcher: Option<(FileWatcher, Reloader)>,
+    control_server: Option<ControlServer>,
+    shut_down: bool,
+}
+
+impl Daemon {
+    pub(crate) fn new(event_loop: EventLoop, central: Central, log_source: LogSource) -> Self {
+        let (inbox_tx, inbox) = loop_channel(event_loop.waker());
+        Self {
+            event_loop,
+            central,
+            log_source,
+            inbox,
+            inbox_tx,
+            signals: None,
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            config_watcher: None,
+            control_server: None,
+            shut_down: false,
+        }
+    }
+
+    pub(crate) fn sender(&self) -> LoopSender<DaemonCommand> {
+        self.inbox_tx.clone()
+    }
+
+    // The first SIGINT or SIGTERM shuts down properly. Should that get stuck, a second one exits right away.
+    // `signals` should be set up early, so that signals meanwhile wait for the loop to pick them up.
+    pub(crate) fn handle_signals(&mut self, mut signals: Signals) -> io::Result<()> {
+        for signal in [SIGINT, SIGTERM] {
+            signal_hook::flag::register_conditional_shutdown(
+                signal,
+                1,
+                self.shutting_down.clone(),
+            )?;
+        }
+        self.event_loop
+            .registry()
+            .register(&mut signals, SIGNALS, Interest::READABLE)?;
+        self.signals = Some(signals);
+        Ok(())
+    }
+
+    pub(crate) fn watch_config(&mut self, watcher: FileWatcher, reload: Reloader) {
+        self.config_watcher = Some((watcher, reload));
+    }
+
+    pub(crate) fn listen_for_commands(&mut self, socket_path: &Path) -> io::Result<()> {
+        self.control_server = Some(ControlServer::bind(
+            socket_path,
+            self.event_loop.registry(),
+        )?);
+        Ok(())
+    }
+
+    // Returns once we've shut down, by then having closed the player. A panic on the way still shuts down properly, since
+    // there's nobody else left to do it, and then carries on unwinding.
+    pub(crate) fn run(mut self) {
+        if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| self.run_until_shut_down())) {
+            self.shut_down();
+            // Takes the control socket with it.
+            drop(self);
+            panic::resume_unwind(panic);
+        }
+    }
+
+    fn run_until_shut_down(&mut self) {
+        while !self.shut_down {
+            self.poll();
+            if self.shut_down {
+                break;
+            }
+            let deadline = self.next_deadline();
+            if let Err(e) = self.event_loop.wait(deadline) {
+                log_error!("The event loop failed, so shutting down: {}", e);
+                self.shut_down();
+            }
+        }
+    }
+
+    // Gives everything a turn, whatever woke us up. Central goes last, so that it can pass on everything it's been told
+    // to the player in one go.
+    fn poll(&mut self) {
+        let now = Instant::now();
+        self.poll_signals();
+        for command in self.inbox.try_iter().collect::<Vec<_>>() {
+            self.handle_command(command);
+        }
+        if let Some((watcher, reload)) = &mut self.config_watcher {
+            if watcher.poll(now) {
+                if let Some(reloaded) = reload() {
+                    self.handle_command(DaemonCommand::Reload(Box::new(reloaded)));
+                }
+            }
+        }
+        self.poll_log_source(now);
+        self.poll_control_server();
+        if !self.shut_down {
+            self.central.poll();
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        earliest([
+            self.central.next_deadline(),
+            self.config_watcher
+                .as_ref()
+                .and_then(|(watcher, _)| watcher.next_deadline()),
+            match &self.log_source {
+                LogSource::Watcher(_) => None,
+                LogSource::Replay(replay) => replay.next_deadline(),
+            },
+        ])
+    }
+
+    fn handle_command(&mut self, command: DaemonCommand) {
+        if self.shut_down {
+            return;
+        }
+        match command {
+            DaemonCommand::Shutdown => self.shut_down(),
+            DaemonCommand::Reload(reloaded) => {
+                let (settings, matcher) = *reloaded;
+                if let LogSource::Watcher(log_watcher) = &mut self.log_source {
+                    log_watcher.set_matcher(matcher);
+                }
+                self.central
+                    .handle_command(CentralCommand::ConfigReloaded(settings));
+            }
+        }
+    }
+
+    fn shut_down(&mut self) {
+        if !self.shut_down {
+            self.shut_down = true;
+            self.central.handle_command(CentralCommand::Shutdown);
+        }
+    }
+
+    fn poll_signals(&mut self) {
+        let Some(signals) = &mut self.signals else {
+            return;
+        };
+        let received: Vec<i32> = signals.pending().collect();
+        for signal in received {
+            let name = if signal == SIGINT {
+                "SIGINT"
+            } else {
+                "SIGTERM"
+            };
+            log_info!("Received {}, shutting down.", name);
+            self.shutting_down.store(true, Ordering::SeqCst);
+            self.shut_down();
+        }
+    }
+
+    fn poll_log_source(&mut self, now: Instant) {
+        let commands = match &mut self.log_source {
+            LogSource::Watcher(log_watcher) => match log_watcher.poll() {
+                Ok(commands) => commands,
+                Err(e) => {
+                    log_error!("Failed to read the log file, so shutting down: {}", e);
+                    self.shut_down();
+                    return;
+                }
+            },
+            LogSource::Replay(replay) => replay.poll(now),
+        };
+        for command in commands {
+            if self.shut_down {
+                return;
+            }
+            if matches!(self.log_source, LogSource::Watcher(_)) {
+                log_found(&command);
+            }
+            self.central.handle_command(command);
+        }
+    }
+
+    fn poll_control_server(&mut self) {
+        // Out of the way while we answer, since some commands need all of us.
+        let Some(mut control_server) = self.control_server.take() else {
+            return;
+        };
+        for (client, command) in control_server.poll() {
+            let reply = match command {
+                Ok(ControlCommand::Status) => format!("ok {}", self.central.status()),
+                Ok(_) if self.shut_down => "error shutting down".to_string(),
+                Ok(ControlCommand::Offset(delta)) => {
+                    self.central.adjust_offset(delta);
+                    "ok".to_string()
+                }
+                Ok(ControlCommand::ToggleAttach) => {
+                    self.central.toggle_attach();
+                    "ok".to_string()
+                }
+                Ok(ControlCommand::Reload) => {
+                    self.central.reload();
+                    "ok".to_string()
+                }
+                Ok(ControlCommand::Quit) => {
+                    log_info!("Told to quit over the control socket, shutting down.");
+                    self.shut_down();
+                    "ok".to_string()
+                }
+                Err(e) => format!("error {}", e),
+            };
+            control_server.reply(client, &reply);
+        }
+        control_server.flush();
+        self.control_server = Some(control_server);
+    }
+}
+
+fn log_found(command: &CentralCommand) {
+    match command {
+        CentralCommand::VrcLogWatcherEvent(VrcLogWatcherEvent::FoundUrl(found_url)) => {
+            log_info!("Video URL found: {}", found_url.url);
+        }
+        CentralCommand::VrcLogWatcherEvent(VrcLogWatcherEvent::FoundSeek(found_seek)) => {
+            log_info!("Seek event found: {}", found_seek.seek_offset);
+        }
+        CentralCommand::VrcLogWatcherEvent(VrcLogWatcherEvent::FoundWorldChange(
+            found_world_change,
+        )) => {
+            log_info!("World change found: {}", found_world_change.world_id);
+        }
+        CentralCommand::VrcLogWatcherEvent(VrcLogWatcherEvent::FoundWorldName(
+            found_world_name,
+        )) => {
+            log_info!("World name found: {}", found_world_name.world_name);
+        }
+        _ => {}
+    }
+}
+
+// A panic on the main thread is dealt with by Daemon::run, but one on any other thread, like the file watchers', shuts
+// everything down too, rather than leaving the rest running without it. Either way, it's logged like anything else, so
+// that it ends up in the log file too.
+pub(crate) fn shut_down_on_panic(daemon_tx: LoopSender<DaemonCommand>) {
+    panic::set_hook(Box::new(move |info| {
+        log_error!("{}", info);
+        // If the daemon is gone, or it's the one panicking, we're shutting down anyway.
+        let _ = daemon_tx.send(DaemonCommand::Shutdown);
+    }));
+}
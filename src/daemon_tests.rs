@@ -0,0 +1,71 @@
+#[cfg(test)]
+mod daemon {
+    use std::{io::Write, os::unix::net::UnixStream, thread};
+
+    use crate::{
+        central::{Central, SyncSettings},
+        clock::SystemClock,
+        daemon::{Daemon, LogSource},
+        event_loop::EventLoop,
+        mock_backend::{BackendCall, MockBackend},
+        vrc_log_reader::{builtin_player_definitions, LogMatcher, VrcLogWatcher},
+    };
+
+    #[test]
+    fn it_still_shuts_down_properly_after_a_panic() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("output_log.txt");
+        let state_file_path = dir.path().join("state.json");
+        let control_socket_path = dir.path().join("control.sock");
+        std::fs::write(&log_path, "").unwrap();
+        let backend = MockBackend::default();
+
+        let thread = {
+            let backend = backend.clone();
+            let state_file_path = state_file_path.clone();
+            let control_socket_path = control_socket_path.clone();
+            let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+            let thread = thread::spawn(move || {
+                let event_loop = EventLoop::new().unwrap();
+                let central = Central::new(
+                    log_path.clone(),
+                    Some(state_file_path),
+                    Box::new(SystemClock),
+                    SyncSettings::default(),
+                    Box::new(backend),
+                );
+                let matcher = LogMatcher::new(builtin_player_definitions(), None);
+                let log_watcher =
+                    VrcLogWatcher::new(log_path, 0, matcher, event_loop.waker()).unwrap();
+                let mut daemon = Daemon::new(
+                    event_loop,
+                    central,
+                    LogSource::Watcher(Box::new(log_watcher)),
+                );
+                daemon.listen_for_commands(&control_socket_path).unwrap();
+                ready_tx.send(()).unwrap();
+                daemon.run();
+            });
+            ready_rx.recv().unwrap();
+            thread
+        };
+
+        assert_eq!(backend.take_calls(), vec![BackendCall::Start]);
+
+        // Adjusting the offset shows it, which is when the backend panics.
+        backend.panic_on_next_call();
+        let mut stream = UnixStream::connect(&control_socket_path).unwrap();
+        writeln!(stream, "offset 0.1").unwrap();
+        assert!(thread.join().is_err());
+
+        assert_eq!(
+            backend.take_calls(),
+            vec![
+                BackendCall::ShowText("Offset: +0.10s".to_string()),
+                BackendCall::Shutdown,
+            ]
+        );
+        assert!(state_file_path.exists());
+        assert!(!control_socket_path.exists());
+    }
+}
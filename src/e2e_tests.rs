@@ -0,0 +1,549 @@
+// The whole daemon, minus mpv: the real log watcher tails a log file we write to as we go, and the real mpv backend talks
+// to a fake mpv that writes down every command it gets.
+#[cfg(test)]
+mod e2e {
+    use std::{
+        fs::{File, OpenOptions},
+        io::{BufRead, BufReader, Write},
+        os::unix::net::{UnixListener, UnixStream},
+        path::{Path, PathBuf},
+        sync::{mpsc, Arc, Mutex},
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    };
+
+    use chrono::{DateTime, Local};
+    use regex::Regex;
+    use serde_json::{json, Value};
+    use tempfile::TempDir;
+
+    use crate::{
+        central::{Central, SyncSettings},
+        clock::Clock,
+        daemon::{self, DaemonCommand, LogSource},
+        event_loop::{EventLoop, LoopSender},
+        mpv_commander::{MpvBackend, MpvOptions},
+        persistence::{load_state, PersistedState},
+        vrc_log_reader::{builtin_player_definitions, parse_timestamp, LogMatcher, VrcLogWatcher},
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    const JOIN_A: &str = "2024.07.22 17:40:00 Log        -  [Behaviour] Joining wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~region(us)";
+    const JOIN_B: &str = "2024.07.22 17:49:00 Log        -  [Behaviour] Joining wrld_4cf554b4-430c-4f8f-b53e-1f294eed230b:67890~region(eu)";
+    const URL_A: &str = "https://youtu.be/zL3wWykAKfs";
+    const URL_B: &str = "https://example.net/video.mp4";
+
+    // It's always 17:50:00, so the seeks we expect can be worked out exactly.
+    struct FixedClock(DateTime<Local>);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> DateTime<Local> {
+            self.0
+        }
+
+        fn instant_at(&self, at: DateTime<Local>) -> Instant {
+            Instant::now()
+                + at.signed_duration_since(self.0)
+                    .to_std()
+                    .unwrap_or_default()
+        }
+    }
+
+    // Since it's always the same time, waiting out the URL debounce would take forever.
+    fn settings() -> SyncSettings {
+        SyncSettings {
+            url_debounce: Duration::ZERO,
+            ..SyncSettings::default()
+        }
+    }
+
+    fn now() -> DateTime<Local> {
+        parse_timestamp("2024.07.22 17:50:00").unwrap()
+    }
+
+    fn now_playing_line(timestamp: &str, player_name: &str, url: &str) -> String {
+        format!("{timestamp} Log        -  [ATA | TVManagerV2 ({player_name})] [AVPro1080p] Now Playing: {url}")
+    }
+
+    fn seek_line(timestamp: &str, player_name: &str, offset: f64) -> String {
+        format!("{timestamp} Log        -  [ATA | TVManagerV2 ({player_name})] Sync enforcement requested. Updating to {offset}")
+    }
+
+    fn seek(position: f64) -> Value {
+        json!(["seek", position, "absolute"])
+    }
+
+    fn loadfile(url: &str) -> Value {
+        json!(["loadfile", url])
+    }
+
+    // Speaks just enough of mpv's JSON IPC protocol for MpvBackend.
+    struct FakeMpv {
+        commands: Arc<Mutex<Vec<Value>>>,
+        client: Arc<Mutex<Option<UnixStream>>>,
+    }
+
+    impl FakeMpv {
+        fn start(socket_path: &Path) -> Self {
+            let listener = UnixListener::bind(socket_path).unwrap();
+            let commands = Arc::new(Mutex::new(Vec::new()));
+            let client = Arc::new(Mutex::new(None::<UnixStream>));
+            {
+                let commands = commands.clone();
+                let client = client.clone();
+                thread::spawn(move || {
+                    for stream in listener.incoming() {
+                        let stream = stream.unwrap();
+                        *client.lock().unwrap() = Some(stream.try_clone().unwrap());
+                        for line in BufReader::new(stream).lines() {
+                            let Ok(line) = line else {
+                                break;
+                            };
+                            let request: Value = serde_json::from_str(&line).unwrap();
+                            let command = request["command"].clone();
+                            let mut client = client.lock().unwrap();
+                            let client = client.as_mut().unwrap();
+                            writeln!(client, r#"{{"data":null,"error":"success"}}"#).unwrap();
+                            // like mpv, this restarts playback
+                            if command[0] == "seek" {
+                                writeln!(client, r#"{{"event":"playback-restart"}}"#).unwrap();
+                            }
+                            commands.lock().unwrap().push(command);
+                        }
+                    }
+                });
+            }
+            Self { commands, client }
+        }
+
+        // mpv takes a while to load things. Until this is called, whatever it was told to load is still loading.
+        fn finish_loading(&self) {
+            let mut client = self.client.lock().unwrap();
+            let client = client.as_mut().unwrap();
+            writeln!(client, r#"{{"event":"file-loaded"}}"#).unwrap();
+            writeln!(client, r#"{{"event":"playback-restart"}}"#).unwrap();
+        }
+
+        // Like finish_loading, for a video that's `duration` seconds long, which mpv reports in between.
+        fn finish_loading_with_duration(&self, duration: f64) {
+            let mut client = self.client.lock().unwrap();
+            let client = client.as_mut().unwrap();
+            writeln!(client, r#"{{"event":"file-loaded"}}"#).unwrap();
+            writeln!(
+                client,
+                r#"{{"event":"property-change","id":1,"name":"duration","data":{}}}"#,
+                duration
+            )
+            .unwrap();
+            writeln!(client, r#"{{"event":"playback-restart"}}"#).unwrap();
+        }
+
+        // Waits for at least `count` commands that aren't part of setting up the connection, like `load-script`, and
+        // returns all of those so far.
+        fn wait_for_commands(&self, count: usize) -> Vec<Value> {
+            let deadline = Instant::now() + TIMEOUT;
+            loop {
+                let commands: Vec<Value> = self
+                    .commands
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|command| {
+                        command[0] != "load-script" && command[0] != "observe_property"
+                    })
+                    .cloned()
+                    .collect();
+                if commands.len() >= count {
+                    return commands;
+                }
+                assert!(
+                    Instant::now() < deadline,
+                    "timed out waiting for {} commands, got {:?}",
+                    count,
+                    commands
+                );
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    // Writes lines the way VRChat does, one at a time, as the test goes.
+    struct LogWriter {
+        file: File,
+    }
+
+    impl LogWriter {
+        fn create(path: &Path, lines: &[&str]) -> Self {
+            let mut writer = Self {
+                file: OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .unwrap(),
+            };
+            for line in lines {
+                writer.write_line(line);
+            }
+            writer
+        }
+
+        fn write_line(&mut self, line: &str) {
+            // VRChat separates entries with blank lines
+            write!(self.file, "{}\n\n", line).unwrap();
+            self.file.flush().unwrap();
+        }
+    }
+
+    struct Daemon {
+        _dir: TempDir,
+        mpv: FakeMpv,
+        log: LogWriter,
+        state_file_path: PathBuf,
+        control_socket_path: PathBuf,
+        daemon_tx: LoopSender<DaemonCommand>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl Daemon {
+        // Starts everything like main does, except that mpv is the fake, and it's always the same time.
+        fn start(initial_lines: &[&str]) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let log_path = dir.path().join("output_log_2024-07-22_17-39-00.txt");
+            let socket_path = dir.path().join("mpv.sock");
+            let state_file_path = dir.path().join("state.json");
+            let control_socket_path = dir.path().join("control.sock");
+
+            let log = LogWriter::create(&log_path, initial_lines);
+            let mpv = FakeMpv::start(&socket_path);
+
+            // Central isn't Send, so it's put together on the thread it runs on.
+            let (ready_tx, ready_rx) = mpsc::channel();
+            let thread = {
+                let state_file_path = state_file_path.clone();
+                let control_socket_path = control_socket_path.clone();
+                thread::spawn(move || {
+                    let event_loop = EventLoop::new().unwrap();
+                    let mut central = Central::new(
+                        log_path.clone(),
+                        Some(state_file_path),
+                        Box::new(FixedClock(now())),
+                        settings(),
+                        Box::new(MpvBackend::new(
+                            event_loop.registry().try_clone().unwrap(),
+                            MpvOptions {
+                                socket_path,
+                                attach: true,
+                                ..MpvOptions::default()
+                            },
+                        )),
+                    );
+                    let matcher = LogMatcher::new(builtin_player_definitions(), None);
+                    let start_offset = central.do_initial(&matcher);
+                    let log_watcher =
+                        VrcLogWatcher::new(log_path, start_offset, matcher, event_loop.waker())
+                            .unwrap();
+                    let mut daemon = daemon::Daemon::new(
+                        event_loop,
+                        central,
+                        LogSource::Watcher(Box::new(log_watcher)),
+                    );
+                    daemon.listen_for_commands(&control_socket_path).unwrap();
+                    ready_tx.send(daemon.sender()).unwrap();
+                    daemon.run();
+                })
+            };
+            let daemon_tx = ready_rx.recv_timeout(TIMEOUT).unwrap();
+
+            Self {
+                _dir: dir,
+                mpv,
+                log,
+                state_file_path,
+                control_socket_path,
+                daemon_tx,
+                thread: Some(thread),
+            }
+        }
+
+        // Like a config reload would.
+        fn reload(&self, matcher: LogMatcher) {
+            self.daemon_tx
+                .send(DaemonCommand::Reload(Box::new((settings(), matcher))))
+                .unwrap();
+            // The daemon reads its inbox before anything else, so once it's answered this, anything written to the log
+            // from here on is read with the new matcher.
+            self.ask("status");
+        }
+
+        // Like main does on SIGINT. The daemon has to have stopped within the timeout.
+        fn shut_down(&mut self) {
+            self.daemon_tx.send(DaemonCommand::Shutdown).unwrap();
+            self.wait_until_stopped();
+        }
+
+        fn wait_until_stopped(&mut self) {
+            let thread = self.thread.take().unwrap();
+            let (done_tx, done_rx) = mpsc::channel();
+            thread::spawn(move || done_tx.send(thread.join().is_ok()));
+            assert_eq!(done_rx.recv_timeout(TIMEOUT), Ok(true));
+        }
+
+        // Asks over the control socket, and returns the reply, without its line ending.
+        fn ask(&self, command: &str) -> String {
+            let mut stream = UnixStream::connect(&self.control_socket_path).unwrap();
+            stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+            writeln!(stream, "{}", command).unwrap();
+            let mut reply = String::new();
+            BufReader::new(stream).read_line(&mut reply).unwrap();
+            reply.trim_end().to_string()
+        }
+
+        // Central saves its state whenever it changes, so this is how we know it's seen a line that had no visible
+        // effect on mpv.
+        fn wait_for_state(&self, predicate: impl Fn(&PersistedState) -> bool) {
+            let deadline = Instant::now() + TIMEOUT;
+            while !load_state(&self.state_file_path).is_some_and(|state| predicate(&state)) {
+                assert!(Instant::now() < deadline, "timed out waiting for state");
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    #[test]
+    fn it_joins_mid_video() {
+        let daemon = Daemon::start(&[
+            JOIN_A,
+            &now_playing_line("2024.07.22 17:48:43", "ProTV Hangout (1)", URL_A),
+            &seek_line("2024.07.22 17:48:44", "ProTV Hangout (1)", 44.96499),
+        ]);
+
+        assert_eq!(daemon.mpv.wait_for_commands(1), vec![loadfile(URL_A)]);
+        daemon.mpv.finish_loading();
+        // 76 seconds after the seek line
+        assert_eq!(
+            daemon.mpv.wait_for_commands(2),
+            vec![loadfile(URL_A), seek(44.96499 + 76.0)]
+        );
+    }
+
+    #[test]
+    fn it_joins_after_the_video_ended() {
+        let daemon = Daemon::start(&[
+            JOIN_A,
+            &now_playing_line("2024.07.22 17:45:00", "ProTV Hangout (1)", URL_A),
+        ]);
+
+        assert_eq!(daemon.mpv.wait_for_commands(1), vec![loadfile(URL_A)]);
+        // the video is 3 minutes long, but started 5 minutes ago
+        daemon.mpv.finish_loading_with_duration(180.0);
+        assert_eq!(
+            daemon.mpv.wait_for_commands(4),
+            vec![
+                loadfile(URL_A),
+                json!(["set_property", "pause", true]),
+                seek(179.5),
+                json!(["show-text", "Ended"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_holds_seeks_until_loaded() {
+        let mut daemon = Daemon::start(&[JOIN_A]);
+
+        daemon.log.write_line(&now_playing_line(
+            "2024.07.22 17:49:00",
+            "ProTV Hangout (1)",
+            URL_A,
+        ));
+        assert_eq!(daemon.mpv.wait_for_commands(1), vec![loadfile(URL_A)]);
+
+        daemon
+            .log
+            .write_line(&seek_line("2024.07.22 17:49:10", "ProTV Hangout (1)", 30.0));
+        daemon.wait_for_state(|state| {
+            state
+                .now_playing
+                .as_ref()
+                .is_some_and(|now_playing| now_playing.additional_offset == 30.0)
+        });
+        daemon.mpv.finish_loading();
+
+        // only the one seek, from the seek line, not from the URL
+        assert_eq!(
+            daemon.mpv.wait_for_commands(2),
+            vec![loadfile(URL_A), seek(30.0 + 50.0)]
+        );
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(daemon.mpv.wait_for_commands(2).len(), 2);
+    }
+
+    #[test]
+    fn it_stops_on_world_change() {
+        let mut daemon = Daemon::start(&[
+            JOIN_A,
+            &now_playing_line("2024.07.22 17:48:00", "ProTV Hangout (1)", URL_A),
+        ]);
+        daemon.mpv.wait_for_commands(1);
+        daemon.mpv.finish_loading();
+        daemon.mpv.wait_for_commands(2);
+
+        daemon.log.write_line(JOIN_B);
+        // the old world's player is forgotten, so its seeks are too
+        daemon.log.write_line(&seek_line(
+            "2024.07.22 17:49:05",
+            "ProTV Hangout (1)",
+            100.0,
+        ));
+        daemon.log.write_line(&now_playing_line(
+            "2024.07.22 17:49:30",
+            "Theatre 1 TVManager",
+            URL_B,
+        ));
+        daemon.mpv.wait_for_commands(4);
+        daemon.mpv.finish_loading();
+
+        assert_eq!(
+            daemon.mpv.wait_for_commands(5),
+            vec![
+                loadfile(URL_A),
+                seek(120.0),
+                json!(["stop"]),
+                loadfile(URL_B),
+                seek(30.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_switches_players() {
+        let mut daemon = Daemon::start(&[
+            JOIN_A,
+            &now_playing_line("2024.07.22 17:49:00", "ProTV Hangout (1)", URL_A),
+        ]);
+        daemon.mpv.wait_for_commands(1);
+        daemon.mpv.finish_loading();
+        daemon.mpv.wait_for_commands(2);
+
+        // a seek from another player in the same world is about some other video
+        daemon
+            .log
+            .write_line(&seek_line("2024.07.22 17:49:05", "ProTV Lobby", 500.0));
+        // until that player starts something new, and we follow it instead
+        daemon.log.write_line(&now_playing_line(
+            "2024.07.22 17:49:20",
+            "ProTV Lobby",
+            URL_B,
+        ));
+        daemon.mpv.wait_for_commands(3);
+        daemon
+            .log
+            .write_line(&seek_line("2024.07.22 17:49:40", "ProTV Lobby", 10.0));
+        daemon.wait_for_state(|state| {
+            state
+                .now_playing
+                .as_ref()
+                .is_some_and(|now_playing| now_playing.additional_offset == 10.0)
+        });
+        daemon.mpv.finish_loading();
+
+        assert_eq!(
+            daemon.mpv.wait_for_commands(4),
+            vec![
+                loadfile(URL_A),
+                seek(60.0),
+                loadfile(URL_B),
+                seek(10.0 + 20.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_picks_up_a_reloaded_matcher() {
+        let mut daemon = Daemon::start(&[JOIN_A]);
+
+        daemon.reload(LogMatcher::new(
+            builtin_player_definitions(),
+            Some(Regex::new("^Theatre").unwrap()),
+        ));
+        daemon.log.write_line(&now_playing_line(
+            "2024.07.22 17:49:00",
+            "ProTV Hangout (1)",
+            URL_A,
+        ));
+        daemon.log.write_line(&now_playing_line(
+            "2024.07.22 17:49:30",
+            "Theatre 1 TVManager",
+            URL_B,
+        ));
+
+        assert_eq!(daemon.mpv.wait_for_commands(1), vec![loadfile(URL_B)]);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(daemon.mpv.wait_for_commands(1).len(), 1);
+    }
+
+    #[test]
+    fn it_shuts_down() {
+        let mut daemon = Daemon::start(&[
+            JOIN_A,
+            &now_playing_line("2024.07.22 17:49:00", "ProTV Hangout (1)", URL_A),
+        ]);
+        daemon.mpv.wait_for_commands(1);
+        daemon.mpv.finish_loading();
+        daemon.mpv.wait_for_commands(2);
+        // nothing that changes what's playing, so it'd usually be a while before this offset was saved
+        daemon
+            .log
+            .write_line("2024.07.22 17:49:30 Log        -  [Behaviour] OnPlayerJoined Someone");
+        let log_len = daemon.log.file.metadata().unwrap().len();
+        thread::sleep(Duration::from_millis(100));
+
+        daemon.shut_down();
+
+        assert_eq!(
+            load_state(&daemon.state_file_path).unwrap().log_offset,
+            log_len
+        );
+        // an mpv we attached to isn't ours to close
+        assert_eq!(
+            daemon.mpv.wait_for_commands(2),
+            vec![loadfile(URL_A), seek(60.0)]
+        );
+    }
+
+    #[test]
+    fn it_takes_commands_over_the_control_socket() {
+        let mut daemon = Daemon::start(&[
+            JOIN_A,
+            &now_playing_line("2024.07.22 17:49:00", "ProTV Hangout (1)", URL_A),
+        ]);
+        daemon.mpv.wait_for_commands(1);
+        daemon.mpv.finish_loading();
+        daemon.mpv.wait_for_commands(2);
+
+        let status = daemon.ask("status");
+        assert!(status.starts_with("ok following"), "{}", status);
+        assert!(status.contains(URL_A), "{}", status);
+
+        // shifting the offset seeks right away, like Alt+] in mpv
+        assert_eq!(daemon.ask("offset 0.5"), "ok");
+        assert_eq!(
+            daemon.mpv.wait_for_commands(4),
+            vec![
+                loadfile(URL_A),
+                seek(60.0),
+                json!(["show-text", "Offset: +0.50s"]),
+                seek(60.5),
+            ]
+        );
+        assert!(daemon.ask("status").contains("offset +0.50s"));
+
+        assert!(daemon.ask("offset soon").starts_with("error"));
+        assert!(daemon.ask("rewind").starts_with("error"));
+
+        assert_eq!(daemon.ask("quit"), "ok");
+        daemon.wait_until_stopped();
+        assert!(!daemon.control_socket_path.exists());
+    }
+}
@@ -0,0 +1,201 @@
+use std::{
+    io::{self, Read, Write},
+    net::Shutdown,
+    sync::{
+        mpsc::{self, Receiver, SendError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use mio::{net::UnixStream, Events, Interest, Poll, Registry, Token, Waker};
+
+// What woke the event loop up. Everything gets polled whenever it wakes up, whatever it was, so these are only there so
+// that mio can tell sources apart.
+pub(crate) const WAKER: Token = Token(0);
+pub(crate) const SIGNALS: Token = Token(1);
+pub(crate) const PLAYER: Token = Token(2);
+pub(crate) const CONTROL_LISTENER: Token = Token(3);
+// Each client of the control socket gets its own, counting up from here.
+pub(crate) const FIRST_CONTROL_CLIENT: Token = Token(16);
+
+// The one place the daemon waits. Sockets are registered with it directly, and anything that happens on another thread,
+// like a file watcher noticing a change, wakes it up through the waker.
+pub(crate) struct EventLoop {
+    poll: Poll,
+    events: Events,
+    waker: Arc<Waker>,
+}
+
+impl EventLoop {
+    pub(crate) fn new() -> io::Result<Self> {
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        Ok(Self {
+            poll,
+            events: Events::with_capacity(64),
+            waker,
+        })
+    }
+
+    pub(crate) fn registry(&self) -> &Registry {
+        self.poll.registry()
+    }
+
+    pub(crate) fn waker(&self) -> Arc<Waker> {
+        self.waker.clone()
+    }
+
+    // Blocks until something happens, or `deadline` passes, whichever comes first.
+    pub(crate) fn wait(&mut self, deadline: Option<Instant>) -> io::Result<()> {
+        let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        match self.poll.poll(&mut self.events, timeout) {
+            // a signal, which the signal source picks up like anything else
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => Ok(()),
+            result => result,
+        }
+    }
+}
+
+// The earliest of some deadlines, if there are any.
+pub(crate) fn earliest(deadlines: impl IntoIterator<Item = Option<Instant>>) -> Option<Instant> {
+    deadlines.into_iter().flatten().min()
+}
+
+// Like mpsc::Sender, but wakes the event loop up, so that it notices right away. For threads that aren't ours, like the
+// file watchers', and for tests.
+pub(crate) struct LoopSender<T> {
+    tx: mpsc::Sender<T>,
+    waker: Arc<Waker>,
+}
+
+impl<T> Clone for LoopSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            waker: self.waker.clone(),
+        }
+    }
+}
+
+impl<T> LoopSender<T> {
+    pub(crate) fn send(&self, message: T) -> Result<(), SendError<T>> {
+        self.tx.send(message)?;
+        // If the loop is gone, we're shutting down anyway.
+        let _ = self.waker.wake();
+        Ok(())
+    }
+}
+
+pub(crate) fn loop_channel<T>(waker: Arc<Waker>) -> (LoopSender<T>, Receiver<T>) {
+    let (tx, rx) = mpsc::channel();
+    (LoopSender { tx, waker }, rx)
+}
+
+// A conversation over a Unix socket, a line at a time, that never blocks. What we say is queued up until the socket
+// can take it, and what we hear is held on to until the rest of its line arrives.
+pub(crate) struct LineConnection {
+    stream: UnixStream,
+    token: Token,
+    outgoing: Vec<u8>,
+    incoming: Vec<u8>,
+    // The other end hung up, or the connection broke.
+    closed: bool,
+}
+
+impl LineConnection {
+    pub(crate) fn register(
+        mut stream: UnixStream,
+        registry: &Registry,
+        token: Token,
+    ) -> io::Result<Self> {
+        registry.register(&mut stream, token, Interest::READABLE | Interest::WRITABLE)?;
+        Ok(Self {
+            stream,
+            token,
+            outgoing: Vec::new(),
+            incoming: Vec::new(),
+            closed: false,
+        })
+    }
+
+    pub(crate) fn token(&self) -> Token {
+        self.token
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    // `text` should end with a newline. It goes out on the next flush.
+    pub(crate) fn queue(&mut self, text: &str) {
+        self.outgoing.extend_from_slice(text.as_bytes());
+    }
+
+    // Sends as much of what's queued as the socket will take right now.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    // For when we're about to hang up, and what's queued has to get out first. Gives up after `timeout`.
+    pub(crate) fn flush_all(&mut self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.flush()?;
+            if self.outgoing.is_empty() {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // Every complete line that's arrived since the last call, without its line ending. Once the other end hangs up, or
+    // the connection breaks, is_closed says so. Whatever was sent before a hangup is still returned.
+    pub(crate) fn read_lines(&mut self) -> io::Result<Vec<String>> {
+        let mut buf = [0u8; 4096];
+        while !self.closed {
+            match self.stream.read(&mut buf) {
+                Ok(0) => self.closed = true,
+                Ok(bytes_read) => self.incoming.extend_from_slice(&buf[..bytes_read]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.closed = true;
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut lines = Vec::new();
+        while let Some(newline) = self.incoming.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.incoming.drain(..=newline).collect();
+            lines.push(
+                String::from_utf8_lossy(&line)
+                    .trim_end_matches(['\r', '\n'])
+                    .to_string(),
+            );
+        }
+        Ok(lines)
+    }
+
+    pub(crate) fn close(mut self, registry: &Registry) {
+        // It's going away either way.
+        let _ = registry.deregister(&mut self.stream);
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+// Set this like RUST_LOG, e.g. `info,mpv_commander=trace`. Overrides `logging.level` in the config.
+pub(crate) const LOG_FILTER_ENV_VAR: &str = "VRC_AVPRO_SUCKS_LOG";
+
+// Once the log file gets this big, it's moved aside and a new one started.
+const MAX_LOG_FILE_SIZE: u64 = 5 * 1024 * 1024;
+
+// How many moved-aside log files to keep, as vrc-avpro-sucks.log.1 (the newest) and up.
+const KEPT_LOG_FILES: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!("unknown log level {:?}", s)),
+        }
+    }
+}
+
+// Which messages make it to the terminal. Like RUST_LOG, but only by level, and targets are our module names, like
+// `central`, `mpv_commander` or `vrc_log_reader`. Things logged from main.rs are under `main`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LogFilter {
+    default: Level,
+    targets: Vec<(String, Level)>,
+}
+
+impl LogFilter {
+    pub(crate) const DEFAULT: LogFilter = LogFilter {
+        default: Level::Info,
+        targets: Vec::new(),
+    };
+
+    pub(crate) fn enables(&self, level: Level, target: &str) -> bool {
+        let max_level = self
+            .targets
+            .iter()
+            .rev()
+            .find(|(filter_target, _)| filter_target == target)
+            .map_or(self.default, |(_, level)| *level);
+        level <= max_level
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = LogFilter::DEFAULT;
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => filter
+                    .targets
+                    .push((target.trim().to_string(), level.trim().parse()?)),
+                None => filter.default = directive.parse()?,
+            }
+        }
+        Ok(filter)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogFormat {
+    Text,
+    // One JSON object per line, for feeding into other tools.
+    Json,
+}
+
+// A log file that moves itself aside once it's big enough, so it never grows without bound.
+pub(crate) struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+}
+
+impl RotatingFile {
+    pub(crate) fn open(path: PathBuf, max_size: u64) -> io::Result<Self> {
+        // The directory may be the user's own, so leave its permissions alone if it's already there.
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+        })
+    }
+
+    pub(crate) fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 + 1 > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated_path = |n: u32| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", n));
+            PathBuf::from(path)
+        };
+        for n in (1..KEPT_LOG_FILES).rev() {
+            if rotated_path(n).exists() {
+                fs::rename(rotated_path(n), rotated_path(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(1))?;
+        *self = Self::open(self.path.clone(), self.max_size)?;
+        Ok(())
+    }
+}
+
+struct Logger {
+    filter: LogFilter,
+    format: LogFormat,
+    file: Option<RotatingFile>,
+}
+
+// Until main sets things up, messages go to the terminal with the default filter.
+static LOGGER: Mutex<Logger> = Mutex::new(Logger {
+    filter: LogFilter::DEFAULT,
+    format: LogFormat::Text,
+    file: None,
+});
+
+// `log_file` gets everything down to debug, whatever the filter says, so there's something to attach to a bug report.
+pub(crate) fn init(filter: LogFilter, format: LogFormat, log_file: Option<&Path>) {
+    let file = log_file.and_then(|path| {
+        match RotatingFile::open(path.to_path_buf(), MAX_LOG_FILE_SIZE) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                // can't log this the usual way, since that needs the lock we're about to take
+                eprintln!("Failed to open log file {:#?}: {}", path, e);
+                None
+            }
+        }
+    });
+    let mut logger = LOGGER.lock().unwrap_or_else(|e| e.into_inner());
+    *logger = Logger {
+        filter,
+        format,
+        file,
+    };
+}
+
+// For when the config is reloaded. Everything else about logging stays as init left it.
+pub(crate) fn set_filter(filter: LogFilter) {
+    LOGGER.lock().unwrap_or_else(|e| e.into_inner()).filter = filter;
+}
+
+// What the macros in common.rs expand to. `module_path` is turned into a target here, rather than in the macros, so
+// that the macros stay short.
+pub(crate) fn log(level: Level, module_path: &str, file: &str, line: u32, args: fmt::Arguments) {
+    let target = target_for(module_path);
+    let mut logger = LOGGER.lock().unwrap_or_else(|e| e.into_inner());
+    let to_terminal = logger.filter.enables(level, target);
+    let to_file = logger.file.is_some() && (to_terminal || level <= Level::Debug);
+    if !to_terminal && !to_file {
+        return;
+    }
+
+    let formatted = format_record(logger.format, level, target, file, line, &args.to_string());
+    if to_terminal {
+        eprintln!("{}", formatted);
+    }
+    if let (true, Some(log_file)) = (to_file, &mut logger.file) {
+        if let Err(e) = log_file.write_line(&formatted) {
+            eprintln!(
+                "Failed to write to log file, so not writing to it anymore: {}",
+                e
+            );
+            logger.file = None;
+        }
+    }
+}
+
+// `vrc_avpro_sucks::mpv_commander` is `mpv_commander`, and the crate root is `main`.
+pub(crate) fn target_for(module_path: &str) -> &str {
+    match module_path.split_once("::") {
+        Some((_, module)) => module,
+        None => "main",
+    }
+}
+
+pub(crate) fn format_record(
+    format: LogFormat,
+    level: Level,
+    target: &str,
+    file: &str,
+    line: u32,
+    message: &str,
+) -> String {
+    let time = Local::now();
+    match format {
+        LogFormat::Text => format!(
+            "{} [{}][{}][{}:{}] {}",
+            time.format("%Y.%m.%d %H:%M:%S%.3f"),
+            level.as_str(),
+            target,
+            file,
+            line,
+            message
+        ),
+        LogFormat::Json => json!({
+            "time": time.to_rfc3339(),
+            "level": level.as_str(),
+            "target": target,
+            "file": file,
+            "line": line,
+            "message": message,
+        })
+        .to_string(),
+    }
+}
@@ -0,0 +1,90 @@
+#[cfg(test)]
+mod logging {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use serde_json::Value;
+
+    use crate::logging::{format_record, target_for, Level, LogFilter, LogFormat, RotatingFile};
+
+    #[test]
+    fn it_filters_by_target() {
+        let filter: LogFilter = "warn, mpv_commander=trace,central=info".parse().unwrap();
+        assert!(filter.enables(Level::Trace, "mpv_commander"));
+        assert!(filter.enables(Level::Info, "central"));
+        assert!(!filter.enables(Level::Debug, "central"));
+        assert!(filter.enables(Level::Warn, "vrc_log_reader"));
+        assert!(!filter.enables(Level::Info, "vrc_log_reader"));
+    }
+
+    #[test]
+    fn it_rejects_unknown_levels() {
+        assert!("verbose".parse::<LogFilter>().is_err());
+        assert!("central=loud".parse::<LogFilter>().is_err());
+        assert_eq!("".parse::<LogFilter>(), Ok(LogFilter::DEFAULT));
+    }
+
+    #[test]
+    fn it_names_targets_after_modules() {
+        assert_eq!(
+            target_for("vrc_avpro_sucks::mpv_commander"),
+            "mpv_commander"
+        );
+        assert_eq!(target_for("vrc_avpro_sucks"), "main");
+    }
+
+    #[test]
+    fn it_formats_json() {
+        let line = format_record(
+            LogFormat::Json,
+            Level::Warn,
+            "central",
+            "src/central.rs",
+            42,
+            "mpv died",
+        );
+        let record: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(record["level"], "WARN");
+        assert_eq!(record["target"], "central");
+        assert_eq!(record["line"], 42);
+        assert_eq!(record["message"], "mpv died");
+    }
+
+    #[test]
+    fn it_rotates_the_log_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vrc-avpro-sucks.log");
+        let mut file = RotatingFile::open(path.clone(), 20).unwrap();
+        for line in ["first line", "second line", "third line", "fourth line"] {
+            file.write_line(line).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth line\n");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("vrc-avpro-sucks.log.1")).unwrap(),
+            "third line\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("vrc-avpro-sucks.log.2")).unwrap(),
+            "second line\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("vrc-avpro-sucks.log.3")).unwrap(),
+            "first line\n"
+        );
+    }
+
+    #[test]
+    fn it_leaves_an_existing_log_dir_as_it_is() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755)).unwrap();
+
+        RotatingFile::open(dir.path().join("vrc-avpro-sucks.log"), 20).unwrap();
+        let mode = fs::metadata(dir.path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+
+        // and creates one that isn't there yet
+        let nested = dir.path().join("logs").join("vrc-avpro-sucks.log");
+        RotatingFile::open(nested.clone(), 20).unwrap();
+        assert!(nested.exists());
+    }
+}
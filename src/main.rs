@@ -1,110 +1,467 @@
-use central::CentralCommand;
-use regex::Regex;
-use signal_hook::{consts::SIGINT, iterator::Signals};
-use std::{
-    env::args,
-    sync::mpsc::{self, Sender},
-    thread::{self, JoinHandle},
-};
-use vrc_log_reader::{VrcLogWatcher, VrcLogWatcherEvent};
-
-use mpv_commander::start_mpv;
+use mio::Registry;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook_mio::v1_0::Signals;
+use std::{env::args, path::PathBuf};
+use vrc_log_reader::{get_latest_vrc_log_file, LogMatcher, VrcLogWatcher};
 
 use crate::{
-    central::Central,
-    mpv_commander::{spawn_mpv_ipc_threads, MpvIpcRequest},
+    central::{Central, SyncSettings},
+    clock::{Clock, ReplayClock, ReplayTimeline, SystemClock},
+    config::{default_config_path, Config, FileWatcher, Player, PlayersFile},
+    control::default_control_socket_path,
+    daemon::{shut_down_on_panic, Daemon, LogSource, Reloader},
+    event_loop::EventLoop,
+    logging::LogFormat,
+    mpv_commander::{install_builtin_script, MpvBackend, MpvOptions},
+    persistence::{default_state_file_path, load_state, lock_state_file},
+    playback_backend::{default_socket_path, PlaybackBackend},
+    printer_backend::PrinterBackend,
+    replay::{find_log_start, Replay},
+    vlc_commander::{VlcBackend, VlcOptions},
 };
 
 mod central;
+mod central_tests;
+mod clock;
 mod common;
+mod config;
+mod config_tests;
+mod control;
+mod control_tests;
+mod daemon;
+mod daemon_tests;
+mod e2e_tests;
+mod event_loop;
+mod logging;
+mod logging_tests;
+#[cfg(test)]
+mod mock_backend;
 mod mpv_commander;
+mod mpv_commander_tests;
+mod persistence;
+mod persistence_tests;
+mod playback_backend;
+mod playback_backend_tests;
+mod printer_backend;
+mod printer_backend_tests;
+mod replay;
+mod replay_tests;
+mod vlc_commander;
+mod vlc_commander_tests;
 mod vrc_log_reader;
 mod vrc_log_reader_tests;
-
-// TODO: join and unwrap all threads
+mod xdg;
 
 fn main() {
-    let main_options = process_args();
+    // The config file, then the environment, then the command line, each overriding the last.
+    // Absolute, so that it can be recognized among the file watcher's events.
+    let config_path =
+        std::path::absolute(default_config_path()).expect("Invalid config file path.");
+    let mut config = Config::load(&config_path)
+        .and_then(|mut config| {
+            config.apply_env(|name| std::env::var(name).ok())?;
+            Ok(config)
+        })
+        .unwrap_or_else(|e| {
+            log_error!("Invalid config: {}", e);
+            std::process::exit(1);
+        });
+    let main_options = process_args(&mut config);
+    if let Err(e) = config.validate() {
+        log_error!("Invalid config: {}", e);
+        print_usage();
+        std::process::exit(1);
+    }
+    config.players_file =
+        std::path::absolute(&config.players_file).expect("Invalid players file path.");
+    let players_file = PlayersFile::load(&config.players_file).unwrap_or_else(|e| {
+        log_error!("Invalid player definitions: {}", e);
+        std::process::exit(1);
+    });
 
-    spawn_signal_handler_thread();
+    if main_options.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+
+    // All of these were checked by validate.
+    logging::init(
+        config.log_filter().unwrap(),
+        config.logging.format,
+        config.log_file_path().as_deref(),
+    );
+    let settings = config.sync_settings().unwrap();
+    let player_name_regex = config.player_name_regex().unwrap();
+    log_debug!("Config file: {:#?}", config_path);
 
-    let mut child = start_mpv();
+    let backend_options = if main_options.dry_run {
+        BackendOptions::DryRun
+    } else {
+        backend_options(&config)
+    };
 
-    // We use channels for cross-thread communication, since moving data ownership around isn't feasible.
-    // For example, MPV IPC and VrcLogReader and VrcLogWatcher can't all own references to each other.
+    // checked when it was loaded
+    let matcher = LogMatcher::new(
+        players_file.player_definitions().unwrap(),
+        player_name_regex,
+    );
+    // Before the initial scan, which can take a moment on a big log. Until the event loop is running, signals wait.
+    let signals = Signals::new([SIGINT, SIGTERM]).expect("Failed to set up signal handling.");
+    let control_socket_path = config
+        .control_socket
+        .clone()
+        .or_else(|| default_control_socket_path().ok());
 
-    // this channel is used to send commands to the mpv process. it is read by the mpv IPC thread.
-    let (mpv_ipc_tx, mpv_ipc_rx) = mpsc::channel::<MpvIpcRequest>();
+    if let Some(replay_log_path) = main_options.replay_log_path {
+        replay(
+            replay_log_path,
+            main_options.replay_speed,
+            settings,
+            backend_options,
+            &matcher,
+            signals,
+        );
+        return;
+    }
 
-    // This is the central dispatch. It runs on the main thread.
-    // When created, it exposes a Sender channel, which can be cloned and handed out to other components, like MPV IPC
-    // and the log reader and watchers.
-    // Then, it receives responses from those other components. Central also holds some "global" state, which
-    // determines how exactly to react to those responses.
-    let mut central = Central::new(mpv_ipc_tx.clone());
-    let central_tx = &central.central_tx.clone();
-    spawn_mpv_ipc_threads(mpv_ipc_rx, central_tx.clone());
+    // Everything happens on the main thread, in one event loop. Central owns the player, and the daemon owns Central
+    // along with everything else that feeds it: the log watcher, the config watcher, signals and the control socket.
+    let event_loop = EventLoop::new().expect("Failed to set up the event loop.");
 
-    let lines_read_initially = central.do_initial(&main_options.player_name_regex);
+    let log_path =
+        get_latest_vrc_log_file(&config.vrchat_log_dir).expect("No VRC log files found.");
+    log_info!("Log file: {:#?}", log_path);
+    // A dry run shouldn't pick up from, or overwrite, where the real thing left off.
+    let mut state_file_path = (!main_options.dry_run).then(default_state_file_path);
+    // Neither should a second instance. This is held until we exit.
+    let _state_file_lock = match state_file_path.as_deref().map(lock_state_file).transpose() {
+        Ok(lock) => lock,
+        Err(e) => {
+            log_warn!(
+                "Not saving or resuming state, since the state file can't be locked: {}",
+                e
+            );
+            state_file_path = None;
+            None
+        }
+    };
 
-    spawn_log_watcher_thread(
-        central_tx.clone(),
-        lines_read_initially,
-        &main_options.player_name_regex,
+    // This is the central dispatch. It's told about everything that happens, one thing at a time, and holds the
+    // "global" state that determines how exactly to react to it.
+    // Central also owns mpv (or VLC), and starts it right away (or connects to it, with --attach-socket).
+    let mut central = Central::new(
+        log_path.clone(),
+        state_file_path.clone(),
+        Box::new(SystemClock),
+        settings,
+        create_backend(
+            backend_options,
+            Box::new(SystemClock),
+            event_loop.registry(),
+        ),
     );
 
-    // this should block forever, until some kind of exit condition is met.
-    central.run_central_dispatch();
+    // If we were restarted while VRChat is still writing to the same log, carry on from where we were.
+    let start_offset = match state_file_path
+        .as_deref()
+        .and_then(load_state)
+        .filter(|state| state.log_path == log_path)
+    {
+        Some(state) => central.resume(state, &matcher),
+        None => central.do_initial(&matcher),
+    };
 
-    // TODO: add an exit condition for central dispatch.
+    let log_watcher = VrcLogWatcher::new(log_path, start_offset, matcher, event_loop.waker())
+        .expect("Failed to watch the log file.");
+    // Moving either file takes a restart, like the other paths in the config.
+    let config_watcher = FileWatcher::new(
+        vec![config_path.clone(), config.players_file.clone()],
+        event_loop.waker(),
+    );
+
+    let mut daemon = Daemon::new(
+        event_loop,
+        central,
+        LogSource::Watcher(Box::new(log_watcher)),
+    );
+    shut_down_on_panic(daemon.sender());
+    daemon
+        .handle_signals(signals)
+        .expect("Failed to set up signal handling.");
+    match config_watcher {
+        Ok(config_watcher) => daemon.watch_config(
+            config_watcher,
+            config_reloader(config_path, config, players_file),
+        ),
+        Err(e) => log_error!("Failed to watch the config for changes: {}", e),
+    }
+    listen_for_commands(&mut daemon, control_socket_path);
 
-    let res = child.wait().expect("mpv process wasn't running");
-    let code = res.code().expect("mpv process didn't exit with a code");
-    log_debug!("mpv process exited with code: {}", code);
+    // This blocks until we're told to shut down, by then having closed the player.
+    daemon.run();
+    log_debug!("Shut down.");
 }
 
+// Not being able to listen isn't worth giving up over, since it's only for scripts.
+fn listen_for_commands(daemon: &mut Daemon, control_socket_path: Option<PathBuf>) {
+    let Some(control_socket_path) = control_socket_path else {
+        log_warn!("Nowhere to put the control socket, so not listening for commands.");
+        return;
+    };
+    match daemon.listen_for_commands(&control_socket_path) {
+        Ok(()) => log_debug!("Control socket: {:#?}", control_socket_path),
+        Err(e) => log_warn!(
+            "Not listening for commands on {:#?}: {}",
+            control_socket_path,
+            e
+        ),
+    }
+}
+
+// Plays back a recorded log, with the same timing as when it was recorded, or faster. Unlike the real thing, this starts
+// from the beginning of the log, and doesn't touch the state file.
+fn replay(
+    log_path: PathBuf,
+    speed: f64,
+    settings: SyncSettings,
+    backend_options: BackendOptions,
+    matcher: &LogMatcher,
+    signals: Signals,
+) {
+    log_info!("Replaying {:#?} at {}x speed.", log_path, speed);
+    let log_start = find_log_start(&log_path)
+        .expect("Failed to read the log to replay.")
+        .expect("No timestamps in the log to replay.");
+    let timeline = ReplayTimeline::start(log_start, speed);
+    let replay =
+        Replay::open(&log_path, matcher, timeline).expect("Failed to read the log to replay.");
+
+    let event_loop = EventLoop::new().expect("Failed to set up the event loop.");
+    let central = Central::new(
+        log_path,
+        None,
+        // Central works out where videos should be as of the log's time, not ours.
+        Box::new(ReplayClock(timeline)),
+        settings,
+        create_backend(
+            backend_options,
+            Box::new(ReplayClock(timeline)),
+            event_loop.registry(),
+        ),
+    );
+    let mut daemon = Daemon::new(event_loop, central, LogSource::Replay(replay));
+    shut_down_on_panic(daemon.sender());
+    daemon
+        .handle_signals(signals)
+        .expect("Failed to set up signal handling.");
+    daemon.run();
+}
+
+// `clock` is only for timestamping what a dry run prints. It should match Central's.
+fn create_backend(
+    backend_options: BackendOptions,
+    clock: Box<dyn Clock>,
+    registry: &Registry,
+) -> Box<dyn PlaybackBackend> {
+    let registry = || {
+        registry
+            .try_clone()
+            .expect("Failed to share the event loop with the player.")
+    };
+    match backend_options {
+        BackendOptions::Mpv(mpv_options) => Box::new(MpvBackend::new(registry(), mpv_options)),
+        BackendOptions::Vlc(vlc_options) => Box::new(VlcBackend::new(registry(), vlc_options)),
+        BackendOptions::DryRun => Box::new(PrinterBackend::new(clock)),
+    }
+}
+
+enum BackendOptions {
+    Mpv(MpvOptions),
+    Vlc(VlcOptions),
+    // Nothing plays. What would have happened is printed instead.
+    DryRun,
+}
+
+fn backend_options(config: &Config) -> BackendOptions {
+    let socket_path = |player_name: &str| match &config.socket_path {
+        Some(socket_path) => socket_path.clone(),
+        None => default_socket_path(player_name).expect("Failed to create the runtime directory."),
+    };
+    match config.player {
+        Player::Mpv => {
+            let mut mpv_options = MpvOptions {
+                executable: config.mpv.path.clone(),
+                extra_args: config.mpv.args.clone(),
+                stderr_log_file: config.mpv.log_file.clone(),
+                quit_on_exit: config.quit_player_on_exit,
+                ..MpvOptions::default()
+            };
+            match &config.mpv.attach_socket {
+                Some(attach_socket) => {
+                    mpv_options.socket_path = attach_socket.clone();
+                    mpv_options.attach = true;
+                }
+                None => mpv_options.socket_path = socket_path("mpv"),
+            }
+            mpv_options.script_path = match &config.mpv.script {
+                // mpv may be running somewhere else, so make it absolute while we still know what it's relative to.
+                Some(script_path) => {
+                    std::path::absolute(script_path).expect("Invalid mpv script path.")
+                }
+                None => install_builtin_script().expect("Failed to write out seconds.lua."),
+            };
+            log_debug!("mpv socket: {:#?}", mpv_options.socket_path);
+            log_debug!("mpv script: {:#?}", mpv_options.script_path);
+            BackendOptions::Mpv(mpv_options)
+        }
+        Player::Vlc => {
+            let vlc_options = VlcOptions {
+                socket_path: socket_path("vlc"),
+                executable: config.vlc.path.clone(),
+                quit_on_exit: config.quit_player_on_exit,
+            };
+            log_debug!("VLC socket: {:#?}", vlc_options.socket_path);
+            BackendOptions::Vlc(vlc_options)
+        }
+    }
+}
+
+// What the command line says to do, besides overriding the config.
 struct MainOptions {
-    player_name_regex: Option<Regex>,
+    // `replay <log file>` plays back a recorded log instead of following VRChat's current one.
+    replay_log_path: Option<PathBuf>,
+    // How much faster than real time to replay.
+    replay_speed: f64,
+    // Print what we'd tell the player to do, instead of starting one.
+    dry_run: bool,
+    // Print the config we'd run with, after all the overrides, and exit.
+    print_config: bool,
 }
 
-fn process_args() -> MainOptions {
+fn process_args(config: &mut Config) -> MainOptions {
     let mut args = args();
     let mut main_options = MainOptions {
-        player_name_regex: None,
+        replay_log_path: None,
+        replay_speed: 1.0,
+        dry_run: false,
+        print_config: false,
     };
 
     args.next(); // skip argv[0]
 
+    let mut args = args.peekable();
+    if args.peek().map(String::as_str) == Some("replay") {
+        args.next();
+        match args.next() {
+            Some(replay_log_path) => main_options.replay_log_path = Some(replay_log_path.into()),
+            None => {
+                print_usage();
+                std::process::exit(1);
+            }
+        }
+    }
+
     while let Some(arg) = args.next() {
         match arg.as_str() {
+            "--speed" if main_options.replay_log_path.is_some() => {
+                match args.next().and_then(|speed| speed.parse::<f64>().ok()) {
+                    Some(speed) if speed > 0.0 => main_options.replay_speed = speed,
+                    _ => {
+                        print_usage();
+                        std::process::exit(1);
+                    }
+                }
+            }
             "--player-name-regex" => {
+                // checked along with the rest of the config
                 if let Some(player_name_regex) = args.next() {
-                    let player_name_regex = Regex::new(&player_name_regex);
-                    match player_name_regex {
-                        Ok(player_name_regex) => {
-                            log_debug!(
-                                "Filtering log processing to only video players that match the regex: {}",
-                                player_name_regex
-                            );
-                            main_options.player_name_regex = Some(player_name_regex);
-                        }
-                        Err(e) => {
-                            log_debug!("Invalid regex value for player-name-regex: {}", e);
-                            print_usage();
-                            std::process::exit(1);
-                        }
-                    }
+                    config.player_name_regex = Some(player_name_regex);
                 } else {
                     print_usage();
                     std::process::exit(1);
                 }
             }
+            "--player" => match args.next().as_deref() {
+                Some("mpv") => config.player = Player::Mpv,
+                Some("vlc") => config.player = Player::Vlc,
+                _ => {
+                    print_usage();
+                    std::process::exit(1);
+                }
+            },
+            "--dry-run" => main_options.dry_run = true,
+            "--attach-socket" => {
+                if let Some(attach_socket) = args.next() {
+                    config.mpv.attach_socket = Some(PathBuf::from(attach_socket));
+                } else {
+                    print_usage();
+                    std::process::exit(1);
+                }
+            }
+            "--socket-path" => {
+                if let Some(socket_path) = args.next() {
+                    config.socket_path = Some(PathBuf::from(socket_path));
+                } else {
+                    print_usage();
+                    std::process::exit(1);
+                }
+            }
+            "--mpv-path" => {
+                if let Some(mpv_path) = args.next() {
+                    config.mpv.path = PathBuf::from(mpv_path);
+                } else {
+                    print_usage();
+                    std::process::exit(1);
+                }
+            }
+            "--mpv-script" => {
+                if let Some(script_path) = args.next() {
+                    config.mpv.script = Some(PathBuf::from(script_path));
+                } else {
+                    print_usage();
+                    std::process::exit(1);
+                }
+            }
+            "--mpv-arg" => {
+                if let Some(mpv_arg) = args.next() {
+                    config.mpv.args.push(mpv_arg);
+                } else {
+                    print_usage();
+                    std::process::exit(1);
+                }
+            }
+            "--mpv-log-file" => {
+                if let Some(mpv_log_file) = args.next() {
+                    config.mpv.log_file = Some(PathBuf::from(mpv_log_file));
+                } else {
+                    print_usage();
+                    std::process::exit(1);
+                }
+            }
+            "--log-level" => {
+                if let Some(log_filter) = args.next() {
+                    config.logging.level = log_filter;
+                } else {
+                    print_usage();
+                    std::process::exit(1);
+                }
+            }
+            "--log-format" => match args.next().as_deref() {
+                Some("text") => config.logging.format = LogFormat::Text,
+                Some("json") => config.logging.format = LogFormat::Json,
+                _ => {
+                    print_usage();
+                    std::process::exit(1);
+                }
+            },
+            "--no-log-file" => config.logging.file = false,
+            "--print-config" => main_options.print_config = true,
             "--help" => {
                 print_usage();
                 std::process::exit(0);
             }
             _ => {
-                log_debug!("Unknown argument: {}", arg);
+                log_error!("Unknown argument: {}", arg);
                 print_usage();
                 std::process::exit(1);
             }
@@ -114,50 +471,61 @@ fn process_args() -> MainOptions {
 }
 
 fn print_usage() {
-    log_debug!("Usage: vrc-avpro-sucks [--player-name-regex <player_name_regex>] [--help]");
-}
-
-fn spawn_log_watcher_thread(
-    central_tx: Sender<CentralCommand>,
-    start_after_line: u64,
-    player_name_regex: &Option<Regex>,
-) -> thread::JoinHandle<()> {
-    let player_name_regex = player_name_regex.clone();
-    thread::spawn(move || {
-        let mut vlw = VrcLogWatcher::from_latest(&player_name_regex);
-        vlw.watch_file(
-            start_after_line,
-            |found_url| {
-                log_debug!("Video URL found: {}", found_url.url);
-                central_tx
-                    .send(CentralCommand::VrcLogWatcherEvent(
-                        VrcLogWatcherEvent::FoundUrl(found_url),
-                    ))
-                    .unwrap();
-            },
-            |found_seek| {
-                log_debug!("Seek event found: {}", found_seek.seek_offset);
-                central_tx
-                    .send(CentralCommand::VrcLogWatcherEvent(
-                        VrcLogWatcherEvent::FoundSeek(found_seek),
-                    ))
-                    .unwrap();
-            },
-        );
-    })
+    eprintln!(
+        "Usage: vrc-avpro-sucks [replay <log file> [--speed <factor>]] [--player mpv|vlc] [--dry-run] [--player-name-regex <player_name_regex>] [--attach-socket <path> | --socket-path <path>] \
+         [--mpv-path <path>] [--mpv-arg <arg>]... [--mpv-log-file <path>] [--mpv-script <path>] \
+         [--log-level <filter>] [--log-format text|json] [--no-log-file] [--print-config] [--help]"
+    );
 }
 
-fn spawn_signal_handler_thread() -> JoinHandle<()> {
-    thread::spawn(|| {
-        let mut signals = Signals::new(&[SIGINT]).expect("Failed to create signal iterator");
-        for signal in signals.forever() {
-            match signal {
-                SIGINT => {
-                    log_debug!("Received SIGINT, exiting");
-                    std::process::exit(0);
-                }
-                _ => unreachable!(),
+// Whenever the config file or the players file is edited, reads them again, and passes on what can change while we're
+// running. If the edit broke something, we say so, and carry on with what we had.
+fn config_reloader(
+    config_path: PathBuf,
+    mut config: Config,
+    mut players_file: PlayersFile,
+) -> Reloader {
+    // Moving the players file takes a restart, like the other paths in the config.
+    let players_file_path = config.players_file.clone();
+    let reload = move || -> Result<(Config, PlayersFile), String> {
+        let mut new_config = Config::load(&config_path)?;
+        new_config.apply_env(|name| std::env::var(name).ok())?;
+        // The command line still wins. It was already checked at startup, so this won't exit.
+        process_args(&mut new_config);
+        new_config.validate()?;
+        new_config.players_file = std::path::absolute(&new_config.players_file)
+            .map_err(|e| format!("players_file: {}", e))?;
+        let new_players_file = PlayersFile::load(&players_file_path)?;
+        Ok((new_config, new_players_file))
+    };
+    Box::new(move || match reload() {
+        // Editors often touch a file more than once per save.
+        Ok((new_config, new_players_file))
+            if new_config == config && new_players_file == players_file =>
+        {
+            None
+        }
+        Ok((new_config, new_players_file)) => {
+            if config.needs_restart_for(&new_config) {
+                log_warn!("Some of the config changes only take effect after a restart.");
             }
+            // All of these were checked by validate.
+            logging::set_filter(new_config.log_filter().unwrap());
+            let reloaded = (
+                new_config.sync_settings().unwrap(),
+                LogMatcher::new(
+                    new_players_file.player_definitions().unwrap(),
+                    new_config.player_name_regex().unwrap(),
+                ),
+            );
+            log_info!("Reloaded the config.");
+            config = new_config;
+            players_file = new_players_file;
+            Some(reloaded)
+        }
+        Err(e) => {
+            log_error!("Invalid config, so keeping the previous one: {}", e);
+            None
         }
     })
 }
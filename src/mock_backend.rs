@@ -0,0 +1,117 @@
+use std::sync::{Arc, Mutex};
+
+use crate::playback_backend::{PlaybackBackend, PlayerExit};
+
+// Everything Central asked the backend to do, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum BackendCall {
+    Start,
+    Load(String),
+    LoadLive(String),
+    GoLive,
+    Seek(f64),
+    SetPaused(bool),
+    Stop,
+    ShowText(String),
+    ShowBadge(Option<String>),
+    Shutdown,
+}
+
+#[derive(Default)]
+struct MockState {
+    calls: Vec<BackendCall>,
+    running: bool,
+    // What handle_disconnect will say, after a simulated exit.
+    exit: Option<PlayerExit>,
+    // Whether the next call panics, like a bug anywhere along the way would.
+    panicking: bool,
+}
+
+// A backend that only writes down what it's told. Clones share the same record, so a test can keep one while Central
+// owns the other.
+#[derive(Clone, Default)]
+pub(crate) struct MockBackend {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockBackend {
+    // Everything recorded since the last call.
+    pub(crate) fn take_calls(&self) -> Vec<BackendCall> {
+        std::mem::take(&mut self.state.lock().unwrap().calls)
+    }
+
+    // Pretends the player went away. Central still has to be told, with PlaybackEvent::Disconnected.
+    pub(crate) fn exit(&self, exit: PlayerExit) {
+        let mut state = self.state.lock().unwrap();
+        state.running = false;
+        state.exit = Some(exit);
+    }
+
+    pub(crate) fn panic_on_next_call(&self) {
+        self.state.lock().unwrap().panicking = true;
+    }
+
+    fn record(&self, call: BackendCall) {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(call);
+        if std::mem::take(&mut state.panicking) {
+            // Without poisoning the lock, so that the test can still look.
+            drop(state);
+            panic!("told to panic");
+        }
+    }
+}
+
+impl PlaybackBackend for MockBackend {
+    fn start(&mut self) {
+        self.record(BackendCall::Start);
+        self.state.lock().unwrap().running = true;
+    }
+
+    fn is_running(&self) -> bool {
+        self.state.lock().unwrap().running
+    }
+
+    fn load(&mut self, url: &str) {
+        self.record(BackendCall::Load(url.to_string()));
+        self.state.lock().unwrap().running = true;
+    }
+
+    fn load_live(&mut self, url: &str) {
+        self.record(BackendCall::LoadLive(url.to_string()));
+        self.state.lock().unwrap().running = true;
+    }
+
+    fn go_live(&mut self) {
+        self.record(BackendCall::GoLive);
+    }
+
+    fn seek(&mut self, position: f64) {
+        self.record(BackendCall::Seek(position));
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.record(BackendCall::SetPaused(paused));
+    }
+
+    fn stop(&mut self) {
+        self.record(BackendCall::Stop);
+    }
+
+    fn show_text(&mut self, text: &str) {
+        self.record(BackendCall::ShowText(text.to_string()));
+    }
+
+    fn show_badge(&mut self, badge: Option<&str>) {
+        self.record(BackendCall::ShowBadge(badge.map(str::to_string)));
+    }
+
+    fn shutdown(&mut self) {
+        self.record(BackendCall::Shutdown);
+        self.state.lock().unwrap().running = false;
+    }
+
+    fn handle_disconnect(&mut self, _generation: u64) -> Option<PlayerExit> {
+        self.state.lock().unwrap().exit.take()
+    }
+}
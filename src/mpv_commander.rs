@@ -1,177 +1,482 @@
 use std::{
-    io::{BufRead, BufReader, Write},
-    os::unix::net::UnixStream,
-    process::{Child, Command, Stdio},
-    sync::mpsc::{Receiver, Sender},
-    thread::{self, JoinHandle},
+    fs::{self, OpenOptions},
+    io,
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::{self, Child, Command, Stdio},
+    time::{Duration, Instant},
 };
 
-use crate::{central::CentralCommand, log_debug, log_error};
+use mio::{net::UnixStream, Registry};
+use serde_json::{json, Value};
 
-#[derive(Debug)]
-pub(crate) enum MpvIpcResponse {
-    FileLoaded,
-    PlaybackRestart,
-}
+use crate::{
+    event_loop::{earliest, LineConnection, PLAYER},
+    log_debug, log_error, log_info, log_trace,
+    playback_backend::{remove_socket, PlaybackBackend, PlaybackEvent, PlayerExit, PlayerReaper},
+    xdg,
+};
 
 #[derive(Debug)]
 pub(crate) enum MpvIpcRequest {
     LoadUrl(String),
+    // mpv's built-in low-latency profile. It applies to the file that's already loaded, and stays applied to whatever's
+    // loaded after it.
+    ApplyLowLatencyProfile,
     Seek(f64),
+    SetPause(bool),
+    Stop,
+    ShowText(String),
+    // None takes it down.
+    ShowBadge(Option<String>),
+    Quit,
+    // Only needed for an mpv we didn't start ourselves, since otherwise it gets the script on the command line.
+    LoadScript(PathBuf),
+    // mpv reports the property's value right away, and then whenever it changes, as a property-change event.
+    ObserveProperty(&'static str),
 }
 
-impl Into<String> for MpvIpcRequest {
-    fn into(self) -> String {
-        match self {
-            // not proper json encoding, but it works
-            MpvIpcRequest::LoadUrl(url) => {
-                format!("{{ \"command\": [\"loadfile\", \"{}\"] }}\n", url)
-            }
-            MpvIpcRequest::Seek(timestamp) => {
-                format!(
-                    "{{ \"command\": [\"seek\", {}, \"absolute\"] }}\n",
-                    timestamp
-                )
-            }
+// Everything we have mpv keep us posted on. mpv reports them in this order, and the duration has to come first, since
+// Central goes by it to tell a live stream from a video that's only partially seekable.
+const OBSERVED_PROPERTIES: [&str; 3] = ["duration", "seekable", "partially-seekable"];
+
+// There's only ever one badge, so it's always the same overlay.
+const BADGE_OVERLAY_ID: u64 = 1;
+
+impl From<MpvIpcRequest> for String {
+    fn from(request: MpvIpcRequest) -> Self {
+        let command = match request {
+            MpvIpcRequest::LoadUrl(url) => json!(["loadfile", url]),
+            MpvIpcRequest::ApplyLowLatencyProfile => json!(["apply-profile", "low-latency"]),
+            MpvIpcRequest::Seek(timestamp) => json!(["seek", timestamp, "absolute"]),
+            MpvIpcRequest::SetPause(paused) => json!(["set_property", "pause", paused]),
+            MpvIpcRequest::Stop => json!(["stop"]),
+            MpvIpcRequest::ShowText(text) => json!(["show-text", text]),
+            // bold and red, in the top right corner
+            MpvIpcRequest::ShowBadge(Some(badge)) => json!({
+                "name": "osd-overlay",
+                "id": BADGE_OVERLAY_ID,
+                "format": "ass-events",
+                "data": format!("{{\\an9\\b1\\c&H0000FF&}}{}", badge),
+            }),
+            MpvIpcRequest::ShowBadge(None) => json!({
+                "name": "osd-overlay",
+                "id": BADGE_OVERLAY_ID,
+                "format": "none",
+                "data": "",
+            }),
+            MpvIpcRequest::Quit => json!(["quit"]),
+            MpvIpcRequest::LoadScript(path) => json!(["load-script", path]),
+            // The ID is only needed to stop observing, which we never do.
+            MpvIpcRequest::ObserveProperty(name) => json!(["observe_property", 1, name]),
+        };
+        format!("{}\n", json!({ "command": command }))
+    }
+}
+
+// Turns a line from mpv's IPC socket into something Central cares about, if it is one.
+pub(crate) fn parse_mpv_ipc_response(line: &str) -> Option<PlaybackEvent> {
+    let message: Value = match serde_json::from_str(line) {
+        Ok(message) => message,
+        Err(e) => {
+            log_error!("Failed to parse response from MPV: {}", e);
+            return None;
+        }
+    };
+    match message.get("event").and_then(Value::as_str)? {
+        "playback-restart" => Some(PlaybackEvent::PlaybackRestart),
+        "file-loaded" => Some(PlaybackEvent::FileLoaded),
+        // Without --keep-open, mpv goes idle after this. "stop" and "redirect" mean something else is loaded next.
+        "end-file" => match message.get("reason").and_then(Value::as_str)? {
+            "eof" | "error" => Some(PlaybackEvent::FileEnded),
+            _ => None,
+        },
+        "property-change" => match message.get("name").and_then(Value::as_str)? {
+            // null while nothing's loaded
+            "duration" => Some(PlaybackEvent::Duration(
+                message.get("data").and_then(Value::as_f64),
+            )),
+            "seekable" => Some(PlaybackEvent::Seekable(
+                message.get("data").and_then(Value::as_bool),
+            )),
+            "partially-seekable" => Some(PlaybackEvent::PartiallySeekable(
+                message.get("data").and_then(Value::as_bool),
+            )),
+            _ => None,
+        },
+        "client-message" => {
+            let args = message.get("args").and_then(Value::as_array)?;
+            Some(PlaybackEvent::ClientMessage(
+                args.iter()
+                    .filter_map(|arg| arg.as_str().map(str::to_string))
+                    .collect(),
+            ))
         }
+        _ => None,
     }
 }
 
-pub(crate) fn start_mpv() -> Child {
-    Command::new("mpv")
-        .arg("--script=seconds.lua")
-        .arg("--input-ipc-server=/tmp/mpvsocket")
-        .arg("--force-window")
-        .arg("--idle")
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()
-        .expect("Failed to exec mpv process")
+#[derive(Clone)]
+pub(crate) struct MpvOptions {
+    // Where mpv listens for IPC. Either the one we start, or the one we attach to.
+    pub(crate) socket_path: PathBuf,
+    // Connect to an mpv that someone else started, instead of starting our own.
+    pub(crate) attach: bool,
+    // The Lua script with our keybinds. This has to be absolute, since mpv's working directory isn't necessarily ours.
+    pub(crate) script_path: PathBuf,
+    // The rest only matter for an mpv we start ourselves.
+    pub(crate) executable: PathBuf,
+    // Passed after our own arguments, so these win if they conflict, e.g. `--no-force-window`.
+    pub(crate) extra_args: Vec<String>,
+    // Where mpv's stderr goes. Without this, it's thrown away along with stdout.
+    pub(crate) stderr_log_file: Option<PathBuf>,
+    // Close mpv when we exit, instead of leaving it open.
+    pub(crate) quit_on_exit: bool,
 }
 
-fn send_to_mpv(mpv_ipc_tx: Sender<MpvIpcRequest>, command: MpvIpcRequest) {
-    match mpv_ipc_tx.send(command) {
-        Ok(_) => {}
-        Err(e) => {
-            log_error!("Failed to send command to MPV: {}", e);
+impl Default for MpvOptions {
+    fn default() -> Self {
+        Self {
+            socket_path: PathBuf::new(),
+            attach: false,
+            script_path: PathBuf::new(),
+            executable: PathBuf::from("mpv"),
+            extra_args: Vec::new(),
+            stderr_log_file: None,
+            quit_on_exit: true,
         }
     }
 }
 
-pub(crate) fn mpv_load_url(mpv_ipc_tx: Sender<MpvIpcRequest>, url: &str) {
-    send_to_mpv(mpv_ipc_tx, MpvIpcRequest::LoadUrl(url.to_string()));
+// Compiled in, so that the installed binary doesn't depend on where it's run from.
+const SECONDS_LUA: &str = include_str!("../seconds.lua");
+
+// Writes out the built-in script for mpv to load, and returns where it went.
+pub(crate) fn install_builtin_script() -> io::Result<PathBuf> {
+    let runtime_dir = xdg::runtime_dir();
+    xdg::create_private_dir(&runtime_dir)?;
+    // mpv names a script after its file, and that name shows up in keybinding names, so it has to stay seconds.lua.
+    // Other instances may be writing the same file, so write it elsewhere first, then move it into place.
+    let script_path = runtime_dir.join("seconds.lua");
+    let tmp_path = runtime_dir.join(format!("seconds.lua.{}.tmp", process::id()));
+    fs::write(&tmp_path, SECONDS_LUA)?;
+    fs::rename(&tmp_path, &script_path)?;
+    Ok(script_path)
 }
 
-pub(crate) fn mpv_seek(mpv_ipc_tx: Sender<MpvIpcRequest>, timestamp: f64) {
-    send_to_mpv(mpv_ipc_tx, MpvIpcRequest::Seek(timestamp));
+pub(crate) fn mpv_args(options: &MpvOptions) -> Vec<String> {
+    let mut args = vec![
+        format!("--script={}", options.script_path.display()),
+        format!("--input-ipc-server={}", options.socket_path.display()),
+        "--force-window".to_string(),
+        "--idle".to_string(),
+    ];
+    args.extend(options.extra_args.iter().cloned());
+    args
 }
 
-pub(crate) fn spawn_mpv_ipc_threads(
-    mpv_ipc_rx: Receiver<MpvIpcRequest>,
-    central_tx: Sender<CentralCommand>,
-) -> JoinHandle<()> {
-    thread::spawn(move || {
-        let stream: UnixStream;
-
-        // try to connect to the socket until it works
-        let mut attempts: u32 = 0;
-        loop {
-            if attempts > 40 {
-                log_error!("Failed to connect to MPV socket after ~2s worth of attempts.");
-                std::process::exit(1);
-            }
-            match UnixStream::connect("/tmp/mpvsocket") {
-                Ok(s) => {
-                    stream = s;
-                    log_debug!("Connected to MPV socket.");
-                    break;
-                }
+fn start_mpv(options: &MpvOptions) -> io::Result<Child> {
+    // Whatever's left at this path belongs to something that's gone. Don't connect to it before our mpv has replaced
+    // it.
+    remove_socket(&options.socket_path);
+    let stderr = match &options.stderr_log_file {
+        Some(stderr_log_file) => Stdio::from(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(stderr_log_file)?,
+        ),
+        None => Stdio::null(),
+    };
+    Command::new(&options.executable)
+        .args(mpv_args(options))
+        .stdout(Stdio::null())
+        .stderr(stderr)
+        // Ctrl-C in our terminal is for us. Whether mpv goes with us is up to quit_on_exit.
+        .process_group(0)
+        .spawn()
+}
+
+// Owns the mpv process and the IPC connection to it. When mpv goes away, this is what starts a new one.
+// Or, when attached to an mpv that someone else started, this only ever connects to it, and never starts or kills it.
+pub(crate) struct MpvBackend {
+    // For registering the IPC socket with the event loop, whenever we connect.
+    registry: Registry,
+    options: MpvOptions,
+    // Bumped every time we start mpv, so that reports about a previous instance can be told apart.
+    generation: u64,
+    child: Option<Child>,
+    connection: MpvConnection,
+    started_at: Instant,
+    reaper: PlayerReaper,
+    // How the current instance went away, once the reaper knows.
+    exit: Option<PlayerExit>,
+}
+
+enum MpvConnection {
+    // mpv isn't running, as far as we know.
+    None,
+    // mpv is starting up, and its socket may not be there yet. Requests wait here until it is.
+    Connecting {
+        attempts: u32,
+        next_attempt: Instant,
+        queued: Vec<MpvIpcRequest>,
+    },
+    Connected(LineConnection),
+    // The connection went away, and Central hasn't dealt with that yet. It's told once the reaper's done.
+    Lost,
+}
+
+impl MpvBackend {
+    pub(crate) fn new(registry: Registry, options: MpvOptions) -> Self {
+        Self {
+            registry,
+            options,
+            generation: 0,
+            child: None,
+            connection: MpvConnection::None,
+            started_at: Instant::now(),
+            reaper: PlayerReaper::default(),
+            exit: None,
+        }
+    }
+
+    // Starts mpv first if needed.
+    fn ensure_running(&mut self) {
+        if self.is_running() {
+            return;
+        }
+
+        self.generation += 1;
+        self.started_at = Instant::now();
+        let mut queued = Vec::new();
+        if self.options.attach {
+            log_info!(
+                "Attaching to mpv at {:#?} (instance {}).",
+                self.options.socket_path,
+                self.generation
+            );
+            // Someone else's mpv doesn't have our keybinds yet. This is the first thing sent once connected.
+            queued.push(MpvIpcRequest::LoadScript(self.options.script_path.clone()));
+        } else {
+            log_info!("Starting mpv (instance {}).", self.generation);
+            self.child = match start_mpv(&self.options) {
+                Ok(child) => Some(child),
                 Err(e) => {
-                    log_debug!("MPV socket not ready yet: {}", e);
-                    attempts += 1;
-                    thread::sleep(std::time::Duration::from_millis(50));
+                    // We'll give up on connecting soon, and that's handled like any other crash.
+                    log_error!("Failed to start {:#?}: {}", self.options.executable, e);
+                    None
                 }
-            }
+            };
         }
+        queued.extend(OBSERVED_PROPERTIES.map(MpvIpcRequest::ObserveProperty));
+        self.connection = MpvConnection::Connecting {
+            attempts: 0,
+            next_attempt: Instant::now(),
+            queued,
+        };
+    }
 
-        let stream_for_send = stream.try_clone().expect("Failed to clone MPV socket");
-        let stream_for_recv = stream.try_clone().expect("Failed to clone MPV socket");
-        spawn_mpv_ipc_req_thread(stream_for_send, mpv_ipc_rx);
-        spawn_mpv_ipc_res_thread(stream_for_recv, central_tx.clone());
-    })
-}
+    // Only if mpv is running.
+    fn send(&mut self, request: MpvIpcRequest) {
+        match &mut self.connection {
+            MpvConnection::Connecting { queued, .. } => queued.push(request),
+            MpvConnection::Connected(connection) => {
+                let command = String::from(request);
+                log_trace!("[MPV] > {}", command.trim_end());
+                connection.queue(&command);
+            }
+            MpvConnection::None | MpvConnection::Lost => {}
+        }
+    }
 
-fn spawn_mpv_ipc_req_thread(
-    mut stream: UnixStream,
-    ipc_request_rx: Receiver<MpvIpcRequest>,
-) -> JoinHandle<()> {
-    thread::spawn(move || loop {
-        let command: String;
-        match ipc_request_rx.recv() {
-            Err(_) => {
-                log_debug!(
-                    "Queue of commands to send to mpv ran dry, so stopping the IPC request thread."
-                );
-                break;
+    fn try_connect(&mut self, now: Instant) {
+        let MpvConnection::Connecting {
+            attempts,
+            next_attempt,
+            queued,
+        } = &mut self.connection
+        else {
+            return;
+        };
+        if now < *next_attempt {
+            return;
+        }
+        let connected = UnixStream::connect(&self.options.socket_path)
+            .and_then(|stream| LineConnection::register(stream, &self.registry, PLAYER));
+        match connected {
+            Ok(connection) => {
+                log_debug!("Connected to MPV socket.");
+                let queued = std::mem::take(queued);
+                self.connection = MpvConnection::Connected(connection);
+                for request in queued {
+                    self.send(request);
+                }
             }
-            Ok(read_command) => {
-                command = read_command.into();
+            Err(e) if *attempts < CONNECT_ATTEMPTS => {
+                log_debug!("MPV socket not ready yet: {}", e);
+                *attempts += 1;
+                *next_attempt = now + CONNECT_RETRY_INTERVAL;
+            }
+            Err(_) => {
+                log_error!("Failed to connect to MPV socket after ~2s worth of attempts.");
+                self.lose_connection();
             }
         }
-        command.lines().for_each(|line| {
-            log_debug!("[MPV] > {}", line);
-        });
-        match stream.write_all(command.as_bytes()) {
-            Ok(_) => {}
-            Err(e) => {
-                log_error!("Failed to send command to MPV: {}", e);
-                match e.raw_os_error() {
-                    Some(32) => {
-                        log_debug!("MPV socket closed, so quitting the application.");
-                        std::process::exit(0);
-                    }
-                    _ => {}
+    }
+
+    // Sends whatever's queued, and reads whatever mpv has said.
+    fn talk(&mut self) -> Vec<PlaybackEvent> {
+        let MpvConnection::Connected(connection) = &mut self.connection else {
+            return Vec::new();
+        };
+
+        let mut events = Vec::new();
+        let result = connection.flush().and_then(|()| connection.read_lines());
+        match &result {
+            Ok(lines) => {
+                for line in lines {
+                    log_trace!("[MPV] < {}", line);
+                    events.extend(parse_mpv_ipc_response(line));
                 }
             }
+            Err(e) => log_error!("Failed to talk to MPV: {}", e),
+        }
+        if result.is_err() || connection.is_closed() {
+            log_debug!("MPV socket closed.");
+            if let MpvConnection::Connected(connection) =
+                std::mem::replace(&mut self.connection, MpvConnection::None)
+            {
+                connection.close(&self.registry);
+            }
+            self.lose_connection();
         }
-    })
+        events
+    }
+
+    // Whether mpv's still there or not, it's done talking to us. Central hears about it once it's gone.
+    fn lose_connection(&mut self) {
+        self.connection = MpvConnection::Lost;
+        self.reaper
+            .reap(self.generation, self.child.take(), self.started_at);
+    }
 }
 
-fn spawn_mpv_ipc_res_thread(
-    stream: UnixStream,
-    central_tx: Sender<CentralCommand>,
-) -> JoinHandle<()> {
-    thread::spawn(move || {
-        let mut reader = BufReader::new(stream);
-        loop {
-            let mut line = String::new();
-            match reader.read_line(&mut line) {
-                Ok(bytes_read) => {
-                    if bytes_read == 0 {
-                        log_debug!("MPV socket closed, so quitting the application");
-                        std::process::exit(0);
-                    }
-                    let trimmed_line = line.trim_end();
-                    log_debug!("[MPV] < {}", trimmed_line);
-                    match trimmed_line {
-                        r#"{"event":"playback-restart"}"# => {
-                            central_tx
-                                .send(CentralCommand::MpvIpcEvent(MpvIpcResponse::PlaybackRestart))
-                                .unwrap();
-                        }
-                        r#"{"event":"file-loaded"}"# => {
-                            central_tx
-                                .send(CentralCommand::MpvIpcEvent(MpvIpcResponse::FileLoaded))
-                                .unwrap();
-                        }
-                        _ => {}
-                    }
-                }
-                Err(err) => {
-                    log_error!("Failed to read response from MPV: {}", err);
-                    break;
-                }
+// mpv usually takes a moment to create its socket, after it's started.
+const CONNECT_ATTEMPTS: u32 = 40;
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+// How long to give mpv to take our last words, on the way out.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl PlaybackBackend for MpvBackend {
+    fn start(&mut self) {
+        self.ensure_running();
+    }
+
+    fn is_running(&self) -> bool {
+        matches!(
+            self.connection,
+            MpvConnection::Connecting { .. } | MpvConnection::Connected(_)
+        )
+    }
+
+    fn load(&mut self, url: &str) {
+        self.ensure_running();
+        self.send(MpvIpcRequest::LoadUrl(url.to_string()));
+    }
+
+    fn go_live(&mut self) {
+        self.send(MpvIpcRequest::ApplyLowLatencyProfile);
+    }
+
+    fn seek(&mut self, position: f64) {
+        self.send(MpvIpcRequest::Seek(position));
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.send(MpvIpcRequest::SetPause(paused));
+    }
+
+    fn stop(&mut self) {
+        self.send(MpvIpcRequest::Stop);
+    }
+
+    fn show_text(&mut self, text: &str) {
+        self.send(MpvIpcRequest::ShowText(text.to_string()));
+    }
+
+    fn show_badge(&mut self, badge: Option<&str>) {
+        self.send(MpvIpcRequest::ShowBadge(badge.map(str::to_string)));
+    }
+
+    fn poll(&mut self, now: Instant) -> Vec<PlaybackEvent> {
+        self.try_connect(now);
+        let mut events = self.talk();
+        for (generation, exit) in self.reaper.poll(now) {
+            // Reports about an older instance would only confuse Central.
+            if generation == self.generation {
+                self.exit = Some(exit);
+                events.push(PlaybackEvent::Disconnected(generation));
+            }
+        }
+        events
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        earliest([
+            match &self.connection {
+                MpvConnection::Connecting { next_attempt, .. } => Some(*next_attempt),
+                _ => None,
+            },
+            self.reaper.next_deadline(),
+        ])
+    }
+
+    fn shutdown(&mut self) {
+        // Someone else's mpv is theirs to close.
+        let quit = self.options.quit_on_exit && !self.options.attach;
+        if let MpvConnection::Connected(mut connection) =
+            std::mem::replace(&mut self.connection, MpvConnection::None)
+        {
+            if quit {
+                log_info!("Closing mpv.");
+                connection.queue(&String::from(MpvIpcRequest::Quit));
+            }
+            // Whatever else was queued is worth getting out too.
+            if let Err(e) = connection.flush_all(QUIT_TIMEOUT) {
+                log_error!("Failed to send the last commands to MPV: {}", e);
+            }
+            connection.close(&self.registry);
+        }
+        if quit {
+            if let Some(child) = self.child.take() {
+                self.reaper
+                    .reap(self.generation, Some(child), self.started_at);
             }
+            remove_socket(&self.options.socket_path);
         }
-    })
+        // Along with any that were already on their way out.
+        self.reaper.wait();
+    }
+
+    fn handle_disconnect(&mut self, generation: u64) -> Option<PlayerExit> {
+        if generation != self.generation || !matches!(self.connection, MpvConnection::Lost) {
+            return None;
+        }
+        self.connection = MpvConnection::None;
+        let exit = self.exit.take()?;
+
+        if self.options.attach {
+            // Not ours to restart. Whoever started it can bring it back, and we'll reconnect when there's a new video.
+            log_debug!(
+                "Lost the connection to the attached mpv (instance {}).",
+                generation
+            );
+            return Some(PlayerExit::Closed);
+        }
+
+        log_debug!("mpv (instance {}) is gone.", generation);
+        Some(exit)
+    }
 }
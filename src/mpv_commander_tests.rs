@@ -0,0 +1,170 @@
+#[cfg(test)]
+mod mpv_args {
+    use crate::mpv_commander::{mpv_args, MpvOptions};
+
+    #[test]
+    fn it_puts_extra_args_last() {
+        let options = MpvOptions {
+            socket_path: "/run/user/1000/vrc-avpro-sucks/mpv-1234.sock".into(),
+            script_path: "/run/user/1000/vrc-avpro-sucks/seconds.lua".into(),
+            extra_args: vec!["--profile=vrc".to_string(), "--no-force-window".to_string()],
+            ..MpvOptions::default()
+        };
+        assert_eq!(
+            mpv_args(&options),
+            vec![
+                "--script=/run/user/1000/vrc-avpro-sucks/seconds.lua",
+                "--input-ipc-server=/run/user/1000/vrc-avpro-sucks/mpv-1234.sock",
+                "--force-window",
+                "--idle",
+                "--profile=vrc",
+                "--no-force-window",
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod mpv_ipc {
+    use serde_json::{json, Value};
+
+    use crate::{
+        mpv_commander::{parse_mpv_ipc_response, MpvIpcRequest},
+        playback_backend::PlaybackEvent,
+    };
+
+    fn command(request: MpvIpcRequest) -> Value {
+        let line = String::from(request);
+        assert!(line.ends_with('\n'));
+        serde_json::from_str::<Value>(&line).unwrap()["command"].clone()
+    }
+
+    #[test]
+    fn it_escapes_urls() {
+        let url = r#"https://example.com/watch?v="quoted"&path=C:\videos"#;
+        assert_eq!(
+            command(MpvIpcRequest::LoadUrl(url.to_string())),
+            json!(["loadfile", url])
+        );
+        assert_eq!(
+            command(MpvIpcRequest::Seek(12.5)),
+            json!(["seek", 12.5, "absolute"])
+        );
+    }
+
+    #[test]
+    fn it_parses_events_regardless_of_formatting() {
+        assert_eq!(
+            parse_mpv_ipc_response(r#"{"event":"file-loaded"}"#),
+            Some(PlaybackEvent::FileLoaded)
+        );
+        // newer mpv versions add fields to some events
+        assert_eq!(
+            parse_mpv_ipc_response(r#"{ "event": "playback-restart", "playlist_entry_id": 1 }"#),
+            Some(PlaybackEvent::PlaybackRestart)
+        );
+    }
+
+    #[test]
+    fn it_parses_messages_from_the_keybinds() {
+        assert_eq!(
+            parse_mpv_ipc_response(
+                r#"{"event":"client-message","args":["vrc-avpro-sucks","offset","-0.1"]}"#
+            ),
+            Some(PlaybackEvent::ClientMessage(vec![
+                "vrc-avpro-sucks".to_string(),
+                "offset".to_string(),
+                "-0.1".to_string(),
+            ]))
+        );
+    }
+
+    #[test]
+    fn it_parses_the_duration() {
+        assert_eq!(
+            parse_mpv_ipc_response(
+                r#"{"event":"property-change","id":1,"name":"duration","data":212.345}"#
+            ),
+            Some(PlaybackEvent::Duration(Some(212.345)))
+        );
+        // nothing loaded
+        assert_eq!(
+            parse_mpv_ipc_response(r#"{"event":"property-change","id":1,"name":"duration"}"#),
+            Some(PlaybackEvent::Duration(None))
+        );
+        assert_eq!(
+            parse_mpv_ipc_response(r#"{"data":null,"request_id":0,"error":"success"}"#),
+            None
+        );
+    }
+
+    #[test]
+    fn it_notices_when_a_file_is_done_with() {
+        assert_eq!(
+            parse_mpv_ipc_response(r#"{"event":"end-file","reason":"eof","playlist_entry_id":1}"#),
+            Some(PlaybackEvent::FileEnded)
+        );
+        assert_eq!(
+            parse_mpv_ipc_response(
+                r#"{"event":"end-file","reason":"error","playlist_entry_id":1,"file_error":"loading failed"}"#
+            ),
+            Some(PlaybackEvent::FileEnded)
+        );
+        // replaced by the next one
+        assert_eq!(
+            parse_mpv_ipc_response(r#"{"event":"end-file","reason":"stop","playlist_entry_id":1}"#),
+            None
+        );
+    }
+
+    #[test]
+    fn it_parses_whether_its_seekable() {
+        assert_eq!(
+            parse_mpv_ipc_response(
+                r#"{"event":"property-change","id":1,"name":"seekable","data":false}"#
+            ),
+            Some(PlaybackEvent::Seekable(Some(false)))
+        );
+        assert_eq!(
+            parse_mpv_ipc_response(
+                r#"{"event":"property-change","id":1,"name":"partially-seekable","data":true}"#
+            ),
+            Some(PlaybackEvent::PartiallySeekable(Some(true)))
+        );
+    }
+
+    #[test]
+    fn it_plays_live_streams_with_the_low_latency_profile() {
+        assert_eq!(
+            command(MpvIpcRequest::ApplyLowLatencyProfile),
+            json!(["apply-profile", "low-latency"])
+        );
+    }
+
+    #[test]
+    fn it_puts_badges_in_the_corner() {
+        assert_eq!(
+            command(MpvIpcRequest::ShowBadge(Some("LIVE".to_string()))),
+            json!({
+                "name": "osd-overlay",
+                "id": 1,
+                "format": "ass-events",
+                "data": "{\\an9\\b1\\c&H0000FF&}LIVE",
+            })
+        );
+        assert_eq!(
+            command(MpvIpcRequest::ShowBadge(None))["format"],
+            json!("none")
+        );
+    }
+
+    #[test]
+    fn it_ignores_everything_else() {
+        assert_eq!(
+            parse_mpv_ipc_response(r#"{"data":null,"request_id":0,"error":"success"}"#),
+            None
+        );
+        assert_eq!(parse_mpv_ipc_response(r#"{"event":"idle"}"#), None);
+        assert_eq!(parse_mpv_ipc_response("not json"), None);
+    }
+}
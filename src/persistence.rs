@@ -0,0 +1,89 @@
+use std::{
+    fs::{self, File, TryLockError},
+    io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::{log_trace, log_warn, xdg};
+
+// A snapshot of what Central knows, saved whenever it changes. If the daemon is restarted while VRChat is still
+// writing to the same log file, this lets it pick up where it left off, rather than rescanning the log and possibly
+// guessing differently.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct PersistedState {
+    pub(crate) log_path: PathBuf,
+    // Byte offset just past the last log line Central has seen.
+    pub(crate) log_offset: u64,
+    pub(crate) followed_player: Option<String>,
+    // What the keybinds set. Like everything else here, these only come back for the same log file, so they don't
+    // outlast the VRChat session they were set in.
+    pub(crate) manual_offset: f64,
+    pub(crate) attached: bool,
+    pub(crate) now_playing: Option<PersistedNowPlaying>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct PersistedNowPlaying {
+    pub(crate) url: String,
+    pub(crate) timestamp: DateTime<Local>,
+    pub(crate) additional_offset: f64,
+}
+
+pub(crate) fn default_state_file_path() -> PathBuf {
+    xdg::state_dir().join("state.json")
+}
+
+// Held for as long as we're running, so that a second instance doesn't overwrite our state with its own, or resume from
+// ours. Released when dropped, or if we die.
+pub(crate) struct StateFileLock {
+    _file: File,
+}
+
+// Fails if another instance already has it.
+pub(crate) fn lock_state_file(path: &Path) -> io::Result<StateFileLock> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = File::create(path.with_extension("lock"))?;
+    file.try_lock().map_err(|e| match e {
+        TryLockError::WouldBlock => io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "another instance is already using it",
+        ),
+        TryLockError::Error(e) => e,
+    })?;
+    Ok(StateFileLock { _file: file })
+}
+
+pub(crate) fn load_state(path: &Path) -> Option<PersistedState> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            log_warn!("Failed to read state file {:#?}: {}", path, e);
+            return None;
+        }
+    };
+    match serde_json::from_str(&contents) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            log_warn!("Ignoring unreadable state file {:#?}: {}", path, e);
+            None
+        }
+    }
+}
+
+pub(crate) fn save_state(path: &Path, state: &PersistedState) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Write to a temporary file first, then swap it in, so a crash mid-write can't leave a half-written state file.
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, serde_json::to_vec_pretty(state)?)?;
+    fs::rename(&tmp_path, path)?;
+    log_trace!("Saved state to {:#?}", path);
+    Ok(())
+}
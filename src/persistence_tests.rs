@@ -0,0 +1,49 @@
+#[cfg(test)]
+mod state_file {
+    use chrono::{Local, TimeZone};
+
+    use crate::persistence::{
+        load_state, lock_state_file, save_state, PersistedNowPlaying, PersistedState,
+    };
+
+    #[test]
+    fn it_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("state.json");
+        let state = PersistedState {
+            log_path: "/tmp/output_log_2024-07-22_17-40-00.txt".into(),
+            log_offset: 123456,
+            followed_player: Some("ProTV Hangout (1)".to_string()),
+            manual_offset: -0.25,
+            attached: false,
+            now_playing: Some(PersistedNowPlaying {
+                url: "https://youtu.be/zL3wWykAKfs".to_string(),
+                timestamp: Local.with_ymd_and_hms(2024, 7, 22, 17, 48, 44).unwrap(),
+                additional_offset: 44.96499,
+            }),
+        };
+
+        save_state(&path, &state).unwrap();
+        assert_eq!(load_state(&path), Some(state));
+    }
+
+    #[test]
+    fn it_ignores_missing_or_corrupt_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        assert_eq!(load_state(&path), None);
+
+        std::fs::write(&path, "{ \"log_path\": ").unwrap();
+        assert_eq!(load_state(&path), None);
+    }
+
+    #[test]
+    fn it_only_lets_one_instance_have_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("state.json");
+        let lock = lock_state_file(&path).unwrap();
+        assert!(lock_state_file(&path).is_err());
+        drop(lock);
+        assert!(lock_state_file(&path).is_ok());
+    }
+}
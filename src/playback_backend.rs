@@ -0,0 +1,233 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::{self, Child, ExitStatus},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{log_debug, log_error, log_warn, xdg};
+
+// Whatever actually plays the video. Central decides what should be playing and where, and tells the backend. The
+// backend reports back whenever Central polls it.
+pub(crate) trait PlaybackBackend {
+    // Opens the player, even if there's nothing to play yet. Does nothing if it's already running.
+    fn start(&mut self);
+
+    // Whether the player is running. Central doesn't start the player just to tell it something.
+    fn is_running(&self) -> bool;
+
+    // Starts the player if needed, and loads the URL. Once it's actually playing, the backend sends PlaybackRestart.
+    fn load(&mut self, url: &str);
+
+    // Like load, for a live stream, which is better played as close to live as possible than smoothly.
+    fn load_live(&mut self, url: &str) {
+        self.load(url);
+        self.go_live();
+    }
+
+    // For a stream that only turned out to be live once it was loaded. Plays it as close to live as possible from here
+    // on, without loading it again.
+    fn go_live(&mut self) {}
+
+    // Absolute, in seconds.
+    fn seek(&mut self, position: f64);
+
+    // For holding a video that's already over on its last frame, rather than letting it play.
+    fn set_paused(&mut self, paused: bool);
+
+    // Unloads whatever's playing, but leaves the player open.
+    fn stop(&mut self);
+
+    // A short message on top of the video.
+    fn show_text(&mut self, text: &str);
+
+    // Unlike show_text, stays in a corner of the video until it's replaced, or taken down with None.
+    fn show_badge(&mut self, _badge: Option<&str>) {}
+
+    // Why Central is doing what it does next. Only worth showing if there's no actual video to look at.
+    fn explain(&mut self, _reason: &str) {}
+
+    // Called once, as we exit. Closes the player, if it's ours and we're meant to, and waits for it to go.
+    fn shutdown(&mut self);
+
+    // Called whenever the event loop wakes up, which includes whenever the player's socket has something for us, and
+    // once next_deadline comes around. Does whatever talking to the player there is to do, and returns what the player
+    // had to say. Backends that don't talk to anything have nothing to do here.
+    fn poll(&mut self, _now: Instant) -> Vec<PlaybackEvent> {
+        Vec::new()
+    }
+
+    // When poll should be called next, even if nothing happens on the player's socket by then.
+    fn next_deadline(&self) -> Option<Instant> {
+        None
+    }
+
+    // Called when the backend has reported PlaybackEvent::Disconnected, which it only does once it knows how the player
+    // went away. Says how, without waiting on anything. Returns None if the report is about a player instance that's
+    // already been dealt with.
+    fn handle_disconnect(&mut self, generation: u64) -> Option<PlayerExit>;
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum PlaybackEvent {
+    FileLoaded,
+    // Playback started or resumed after loading or seeking.
+    PlaybackRestart,
+    // The player played to the end of what was loaded, or failed to load it, and unloaded it. Not sent when something
+    // else is loaded over it, or it's stopped.
+    FileEnded,
+    // Where playback is right now, in seconds. Only from backends that can't be trusted to stay in sync on their own.
+    Position(f64),
+    // How long the loaded video is, in seconds, whenever that changes. None while nothing's loaded, or if the player
+    // can't tell. Only from backends that know.
+    Duration(Option<f64>),
+    // Whether the loaded video can be seeked at all, and whether that's only within what the player has cached, like
+    // for most live streams. Only from backends that know.
+    Seekable(Option<bool>),
+    PartiallySeekable(Option<bool>),
+    // Sent by `script-message` in mpv, i.e. from the keybinds in seconds.lua. The first argument is the target.
+    ClientMessage(Vec<String>),
+    // The connection to the given player instance was lost, most likely because it exited, and it's been cleaned up
+    // after.
+    Disconnected(u64),
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum PlayerExit {
+    // The user closed the window or quit the player. Don't bring it back until there's something new to play.
+    Closed,
+    // The player died on its own. It's worth restarting it right away.
+    Crashed,
+    // The player died right after starting. Restarting it immediately would likely just loop.
+    CrashedOnStartup,
+}
+
+// If the player dies within this long of being started, something is probably wrong with it, and restarting it straight
+// away would just make it die again.
+const MIN_UPTIME_FOR_RESPAWN: Duration = Duration::from_secs(5);
+
+// How long to give the player to exit on its own, once its socket has closed, before killing it.
+const EXIT_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+// Nothing wakes the event loop up when a process exits, so one that's on its way out is checked on this often.
+const EXIT_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+// Cleans up after player processes whose sockets have closed, and says how they went away, without holding up the event
+// loop while they go. Usually there's only the one, but a new player can be started before the last one's gone.
+#[derive(Default)]
+pub(crate) struct PlayerReaper {
+    exiting: Vec<ExitingPlayer>,
+}
+
+struct ExitingPlayer {
+    generation: u64,
+    // None if we didn't start it, or failed to.
+    child: Option<Child>,
+    crashed_on_startup: bool,
+    kill_at: Instant,
+    next_check: Instant,
+}
+
+impl PlayerReaper {
+    // Once the given instance's socket has closed. The next poll has a look right away.
+    pub(crate) fn reap(&mut self, generation: u64, child: Option<Child>, started_at: Instant) {
+        let now = Instant::now();
+        self.exiting.push(ExitingPlayer {
+            generation,
+            child,
+            crashed_on_startup: now.duration_since(started_at) < MIN_UPTIME_FOR_RESPAWN,
+            kill_at: now + EXIT_GRACE_PERIOD,
+            next_check: now,
+        });
+    }
+
+    // Each instance that's gone since, and how it went. Any that have had long enough are killed.
+    pub(crate) fn poll(&mut self, now: Instant) -> Vec<(u64, PlayerExit)> {
+        let mut exits = Vec::new();
+        self.exiting.retain_mut(|player| match player.poll(now) {
+            Some(exit) => {
+                exits.push((player.generation, exit));
+                false
+            }
+            None => true,
+        });
+        exits
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.exiting.iter().map(|player| player.next_check).min()
+    }
+
+    // On the way out, when there's nothing else left to do but wait.
+    pub(crate) fn wait(&mut self) {
+        while let Some(deadline) = self.next_deadline() {
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            self.poll(Instant::now());
+        }
+    }
+}
+
+impl ExitingPlayer {
+    fn poll(&mut self, now: Instant) -> Option<PlayerExit> {
+        if now < self.next_check {
+            return None;
+        }
+        let exit_status = match &mut self.child {
+            Some(child) => match child.try_wait() {
+                Ok(Some(status)) => Some(status),
+                Ok(None) if now < self.kill_at => {
+                    self.next_check = (now + EXIT_CHECK_INTERVAL).min(self.kill_at);
+                    return None;
+                }
+                Ok(None) => {
+                    log_warn!(
+                        "The player's socket closed, but the process didn't exit, so killing it."
+                    );
+                    let _ = child.kill();
+                    child.wait().ok()
+                }
+                Err(e) => {
+                    log_error!("Failed to wait for the player to exit: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        Some(self.exit(exit_status))
+    }
+
+    fn exit(&self, exit_status: Option<ExitStatus>) -> PlayerExit {
+        log_debug!(
+            "Player (instance {}) exited: {:?}",
+            self.generation,
+            exit_status
+        );
+        match exit_status {
+            Some(status) if status.success() => PlayerExit::Closed,
+            _ if self.crashed_on_startup => PlayerExit::CrashedOnStartup,
+            _ => PlayerExit::Crashed,
+        }
+    }
+}
+
+// Unique to this instance, so that a second instance, or some other tool using the same player, doesn't end up talking
+// to our player.
+pub(crate) fn default_socket_path(player: &str) -> io::Result<PathBuf> {
+    let runtime_dir = xdg::runtime_dir();
+    xdg::create_private_dir(&runtime_dir)?;
+    Ok(runtime_dir.join(format!("{}-{}.sock", player, process::id())))
+}
+
+// Once we're done with it, so that nothing tries connecting to it later.
+pub(crate) fn remove_socket(socket_path: &Path) {
+    match fs::remove_file(socket_path) {
+        Ok(_) => {
+            log_debug!("Removed socket {:#?}", socket_path);
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => {
+            log_warn!("Failed to remove socket {:#?}: {}", socket_path, e);
+        }
+    }
+}
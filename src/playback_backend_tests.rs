@@ -0,0 +1,74 @@
+#[cfg(test)]
+mod player_reaper {
+    use std::{
+        process::Command,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::playback_backend::{PlayerExit, PlayerReaper};
+
+    fn long_ago() -> Instant {
+        Instant::now() - Duration::from_secs(60)
+    }
+
+    // Polls whenever the reaper asks to be, like the event loop would.
+    fn poll_until_gone(reaper: &mut PlayerReaper) -> Vec<(u64, PlayerExit)> {
+        let mut exits = Vec::new();
+        while let Some(deadline) = reaper.next_deadline() {
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            exits.extend(reaper.poll(Instant::now()));
+        }
+        exits
+    }
+
+    #[test]
+    fn it_says_how_the_player_went() {
+        let mut reaper = PlayerReaper::default();
+        reaper.reap(1, Some(Command::new("true").spawn().unwrap()), long_ago());
+        reaper.reap(2, Some(Command::new("false").spawn().unwrap()), long_ago());
+        reaper.reap(
+            3,
+            Some(Command::new("false").spawn().unwrap()),
+            Instant::now(),
+        );
+        let mut exits = poll_until_gone(&mut reaper);
+        exits.sort_by_key(|(generation, _)| *generation);
+        assert_eq!(
+            exits,
+            vec![
+                (1, PlayerExit::Closed),
+                (2, PlayerExit::Crashed),
+                (3, PlayerExit::CrashedOnStartup),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_doesnt_wait_for_a_player_that_wont_exit() {
+        let mut reaper = PlayerReaper::default();
+        reaper.reap(
+            1,
+            Some(Command::new("sleep").arg("60").spawn().unwrap()),
+            long_ago(),
+        );
+        let started_at = Instant::now();
+        assert_eq!(reaper.poll(Instant::now()), vec![]);
+        assert!(started_at.elapsed() < Duration::from_millis(500));
+        assert!(reaper.next_deadline().is_some());
+
+        // until it's had long enough
+        assert_eq!(
+            reaper.poll(Instant::now() + Duration::from_secs(3)),
+            vec![(1, PlayerExit::Crashed)]
+        );
+        assert_eq!(reaper.next_deadline(), None);
+    }
+
+    #[test]
+    fn it_has_nothing_to_wait_for_without_a_process() {
+        let mut reaper = PlayerReaper::default();
+        reaper.reap(1, None, long_ago());
+        assert_eq!(reaper.poll(Instant::now()), vec![(1, PlayerExit::Crashed)]);
+    }
+}
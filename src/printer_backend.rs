@@ -0,0 +1,108 @@
+use std::time::Instant;
+
+use crate::{
+    clock::Clock,
+    playback_backend::{PlaybackBackend, PlaybackEvent, PlayerExit},
+};
+
+// For --dry-run. Instead of playing anything, prints what it's told to do, and why. Loading is instant, so Central
+// carries on exactly as if a very fast player were attached.
+pub(crate) struct PrinterBackend {
+    // During a replay, it's more useful to see the log's time than ours.
+    clock: Box<dyn Clock>,
+    running: bool,
+    // For the next poll.
+    events: Vec<PlaybackEvent>,
+}
+
+impl PrinterBackend {
+    pub(crate) fn new(clock: Box<dyn Clock>) -> Self {
+        Self {
+            clock,
+            running: false,
+            events: Vec::new(),
+        }
+    }
+
+    fn print(&self, what: &str) {
+        println!(
+            "{} {}",
+            self.clock.now().format("%Y.%m.%d %H:%M:%S%.3f"),
+            what
+        );
+    }
+}
+
+impl PlaybackBackend for PrinterBackend {
+    fn start(&mut self) {
+        if !self.running {
+            self.running = true;
+            self.print("start");
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.running
+    }
+
+    fn load(&mut self, url: &str) {
+        self.start();
+        self.print(&format!("load {}", url));
+        self.events
+            .extend([PlaybackEvent::FileLoaded, PlaybackEvent::PlaybackRestart]);
+    }
+
+    fn load_live(&mut self, url: &str) {
+        self.start();
+        self.print(&format!("load live {}", url));
+        self.events
+            .extend([PlaybackEvent::FileLoaded, PlaybackEvent::PlaybackRestart]);
+    }
+
+    fn go_live(&mut self) {
+        self.print("go live");
+    }
+
+    fn seek(&mut self, position: f64) {
+        self.print(&format!("seek {:.3}", position));
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.print(if paused { "pause" } else { "unpause" });
+    }
+
+    fn stop(&mut self) {
+        self.print("stop");
+    }
+
+    fn show_text(&mut self, text: &str) {
+        self.print(&format!("osd {:?}", text));
+    }
+
+    fn show_badge(&mut self, badge: Option<&str>) {
+        match badge {
+            Some(badge) => self.print(&format!("badge {:?}", badge)),
+            None => self.print("no badge"),
+        }
+    }
+
+    fn explain(&mut self, reason: &str) {
+        self.print(&format!("  ({})", reason));
+    }
+
+    fn poll(&mut self, _now: Instant) -> Vec<PlaybackEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn shutdown(&mut self) {
+        if self.running {
+            self.running = false;
+            self.print("quit");
+        }
+    }
+
+    fn handle_disconnect(&mut self, _generation: u64) -> Option<PlayerExit> {
+        // there's nothing to disconnect from
+        None
+    }
+}
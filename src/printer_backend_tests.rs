@@ -0,0 +1,24 @@
+#[cfg(test)]
+mod printer_backend {
+    use std::time::Instant;
+
+    use crate::{
+        clock::SystemClock,
+        playback_backend::{PlaybackBackend, PlaybackEvent},
+        printer_backend::PrinterBackend,
+    };
+
+    #[test]
+    fn it_loads_instantly() {
+        let mut backend = PrinterBackend::new(Box::new(SystemClock));
+        assert!(!backend.is_running());
+
+        backend.load("https://youtu.be/zL3wWykAKfs");
+        assert!(backend.is_running());
+        assert_eq!(
+            backend.poll(Instant::now()),
+            vec![PlaybackEvent::FileLoaded, PlaybackEvent::PlaybackRestart]
+        );
+        assert_eq!(backend.poll(Instant::now()), vec![]);
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    time::Instant,
+};
+
+use crate::{
+    central::CentralCommand,
+    clock::ReplayTimeline,
+    log_debug, log_info,
+    vrc_log_reader::{try_parse_line_timestamp, LogMatcher, LogTail, VrcLogWatcherEvent},
+};
+
+// Where the replay starts: the first timestamp in the log.
+pub(crate) fn find_log_start(
+    log_path: &Path,
+) -> io::Result<Option<chrono::DateTime<chrono::Local>>> {
+    let reader = BufReader::new(File::open(log_path)?);
+    for line in reader.split(b'\n') {
+        if let Some(timestamp) = try_parse_line_timestamp(&String::from_utf8_lossy(&line?)) {
+            return Ok(Some(timestamp));
+        }
+    }
+    Ok(None)
+}
+
+// Feeds a recorded log to Central, like the log watcher would have while VRChat was writing it. Each line is held back
+// until as long after the start as it was originally logged, sped up according to the timeline. The log is finished,
+// so it's read, and matched, all at once, and the event loop only has to wait for each line's turn.
+pub(crate) struct Replay {
+    lines: VecDeque<ReplayedLine>,
+    finished: bool,
+}
+
+struct ReplayedLine {
+    // When it goes out.
+    at: Instant,
+    offset: u64,
+    line: String,
+    event: VrcLogWatcherEvent,
+}
+
+impl Replay {
+    pub(crate) fn open(
+        log_path: &Path,
+        matcher: &LogMatcher,
+        timeline: ReplayTimeline,
+    ) -> io::Result<Self> {
+        let mut tail = LogTail::open(log_path, 0)?;
+        let mut lines = VecDeque::new();
+        let mut at = Instant::now();
+        tail.read_new_lines(|line, offset| {
+            // continuation lines go out along with the line they continue
+            if let Some(timestamp) = try_parse_line_timestamp(line) {
+                at = timeline.wall_time_for(timestamp);
+            }
+            if let Some(event) = matcher.match_line(line) {
+                lines.push_back(ReplayedLine {
+                    at,
+                    offset,
+                    line: line.to_string(),
+                    event,
+                });
+            }
+        })?;
+        Ok(Self {
+            lines,
+            finished: false,
+        })
+    }
+
+    // Whatever lines are due by `now`.
+    pub(crate) fn poll(&mut self, now: Instant) -> Vec<CentralCommand> {
+        let mut commands = Vec::new();
+        while self.lines.front().is_some_and(|line| line.at <= now) {
+            let replayed = self.lines.pop_front().unwrap();
+            log_debug!(
+                "Replaying line at byte {}: {}",
+                replayed.offset,
+                replayed.line
+            );
+            commands.push(CentralCommand::VrcLogWatcherEvent(replayed.event));
+        }
+        if self.lines.is_empty() && !self.finished {
+            self.finished = true;
+            log_info!("Replay finished. Press Ctrl-C to exit.");
+        }
+        commands
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.lines.front().map(|line| line.at)
+    }
+}
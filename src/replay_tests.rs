@@ -0,0 +1,44 @@
+#[cfg(test)]
+mod replay {
+    use std::{fs, time::Duration};
+
+    use crate::{clock::ReplayTimeline, replay::find_log_start, vrc_log_reader::parse_timestamp};
+
+    #[test]
+    fn it_starts_at_the_first_timestamp() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output_log.txt");
+        fs::write(
+            &path,
+            "\n\
+             continued from nowhere\n\
+             2024.07.22 17:40:00 Log        -  [Behaviour] Joining wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~region(us)\n\
+             2024.07.22 17:48:43 Log        -  [ATA | TVManagerV2 (ProTV Hangout (1))] [AVPro1080p] Now Playing: https://youtu.be/zL3wWykAKfs\n",
+        )
+        .unwrap();
+        assert_eq!(
+            find_log_start(&path).unwrap(),
+            Some(parse_timestamp("2024.07.22 17:40:00").unwrap())
+        );
+
+        fs::write(&path, "nothing to see here\n").unwrap();
+        assert_eq!(find_log_start(&path).unwrap(), None);
+    }
+
+    #[test]
+    fn it_spaces_lines_out_by_speed() {
+        let log_start = parse_timestamp("2024.07.22 17:40:00").unwrap();
+        let timeline = ReplayTimeline::start(log_start, 4.0);
+        let wall_start = timeline.wall_time_for(log_start);
+
+        assert_eq!(
+            timeline.wall_time_for(parse_timestamp("2024.07.22 17:41:00").unwrap()) - wall_start,
+            Duration::from_secs(15)
+        );
+        // anything from before the start goes out right away
+        assert_eq!(
+            timeline.wall_time_for(parse_timestamp("2024.07.22 17:39:00").unwrap()),
+            wall_start
+        );
+    }
+}
@@ -0,0 +1,396 @@
+use std::{
+    io,
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use mio::{net::UnixStream, Registry};
+
+use crate::{
+    event_loop::{earliest, LineConnection, PLAYER},
+    log_debug, log_error, log_info, log_trace,
+    playback_backend::{remove_socket, PlaybackBackend, PlaybackEvent, PlayerExit, PlayerReaper},
+};
+
+// VLC's rc interface doesn't tell us anything on its own, so we keep asking it where it is. It doesn't answer at all
+// while nothing is playing.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(crate) struct VlcOptions {
+    // Where VLC's rc interface listens.
+    pub(crate) socket_path: PathBuf,
+    pub(crate) executable: PathBuf,
+    // Close VLC when we exit, instead of leaving it open.
+    pub(crate) quit_on_exit: bool,
+}
+
+#[derive(Debug)]
+pub(crate) enum VlcRequest {
+    Add(String),
+    Seek(f64),
+    Pause,
+    Play,
+    Stop,
+    GetTime,
+    Quit,
+}
+
+impl From<VlcRequest> for String {
+    fn from(request: VlcRequest) -> Self {
+        match request {
+            VlcRequest::Add(url) => format!("add {}\n", url),
+            // rc only takes whole seconds
+            VlcRequest::Seek(position) => format!("seek {}\n", position.max(0.0).round() as u64),
+            // this one toggles, so only send it when playing
+            VlcRequest::Pause => "pause\n".to_string(),
+            VlcRequest::Play => "play\n".to_string(),
+            VlcRequest::Stop => "stop\n".to_string(),
+            VlcRequest::GetTime => "get_time\n".to_string(),
+            VlcRequest::Quit => "quit\n".to_string(),
+        }
+    }
+}
+
+// rc answers `get_time` with whole seconds, possibly after a `> ` prompt. Everything else it says is chatter.
+pub(crate) fn parse_vlc_time(line: &str) -> Option<f64> {
+    line.trim_start_matches(|c: char| c == '>' || c.is_whitespace())
+        .trim_end()
+        .parse::<u64>()
+        .ok()
+        .map(|seconds| seconds as f64)
+}
+
+// rc does say when playback stops, whether because the file ended, failed, or was stopped or replaced. State 5 is the
+// end of the file, and 0 an error.
+pub(crate) fn is_vlc_stop(line: &str) -> bool {
+    line.trim_start_matches(|c: char| c == '>' || c.is_whitespace())
+        .starts_with("status change: ( stop state:")
+}
+
+fn start_vlc(options: &VlcOptions) -> io::Result<Child> {
+    remove_socket(&options.socket_path);
+    Command::new(&options.executable)
+        .arg("--intf=rc")
+        .arg(format!("--rc-unix={}", options.socket_path.display()))
+        .arg("--no-video-title-show")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // like mpv, it's up to quit_on_exit whether VLC goes with us
+        .process_group(0)
+        .spawn()
+}
+
+// Like MpvBackend, but for VLC. There are no keybinds, and no OSD, since rc has no way to do either.
+pub(crate) struct VlcBackend {
+    registry: Registry,
+    options: VlcOptions,
+    // Bumped every time we start VLC, so that reports about a previous instance can be told apart.
+    generation: u64,
+    child: Option<Child>,
+    connection: VlcConnection,
+    started_at: Instant,
+    reaper: PlayerReaper,
+    // How the current instance went away, once the reaper knows.
+    exit: Option<PlayerExit>,
+    paused: bool,
+    // After `add`, there's nothing worth reporting until the time starts moving.
+    is_loading: bool,
+    // Once the time has started moving, until VLC stops on its own. Stopping or replacing it doesn't count.
+    is_playing: bool,
+}
+
+enum VlcConnection {
+    None,
+    // VLC is starting up. Requests wait here until its socket is there.
+    Connecting {
+        attempts: u32,
+        next_attempt: Instant,
+        queued: Vec<VlcRequest>,
+    },
+    Connected {
+        connection: LineConnection,
+        next_get_time: Instant,
+    },
+    // The connection went away, and Central hasn't dealt with that yet. It's told once the reaper's done.
+    Lost,
+}
+
+// VLC takes a while longer than mpv to start up.
+const CONNECT_ATTEMPTS: u32 = 100;
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+// How long to give VLC to take our last words, on the way out.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl VlcBackend {
+    pub(crate) fn new(registry: Registry, options: VlcOptions) -> Self {
+        Self {
+            registry,
+            options,
+            generation: 0,
+            child: None,
+            connection: VlcConnection::None,
+            started_at: Instant::now(),
+            reaper: PlayerReaper::default(),
+            exit: None,
+            paused: false,
+            is_loading: false,
+            is_playing: false,
+        }
+    }
+
+    fn ensure_running(&mut self) {
+        if self.is_running() {
+            return;
+        }
+
+        self.generation += 1;
+        self.started_at = Instant::now();
+        log_info!("Starting VLC (instance {}).", self.generation);
+        self.child = match start_vlc(&self.options) {
+            Ok(child) => Some(child),
+            Err(e) => {
+                // We'll give up on connecting soon, and that's handled like any other crash.
+                log_error!("Failed to start {:#?}: {}", self.options.executable, e);
+                None
+            }
+        };
+        self.is_loading = false;
+        self.is_playing = false;
+        self.connection = VlcConnection::Connecting {
+            attempts: 0,
+            next_attempt: Instant::now(),
+            queued: Vec::new(),
+        };
+    }
+
+    // Only if VLC is running.
+    fn send(&mut self, request: VlcRequest) {
+        match &mut self.connection {
+            VlcConnection::Connecting { queued, .. } => queued.push(request),
+            VlcConnection::Connected { connection, .. } => {
+                match request {
+                    VlcRequest::Add(_) => {
+                        self.is_loading = true;
+                        self.is_playing = false;
+                    }
+                    VlcRequest::Stop => {
+                        self.is_loading = false;
+                        self.is_playing = false;
+                    }
+                    _ => {}
+                }
+                let command = String::from(request);
+                log_trace!("[VLC] > {}", command.trim_end());
+                connection.queue(&command);
+            }
+            VlcConnection::None | VlcConnection::Lost => {}
+        }
+    }
+
+    fn try_connect(&mut self, now: Instant) {
+        let VlcConnection::Connecting {
+            attempts,
+            next_attempt,
+            queued,
+        } = &mut self.connection
+        else {
+            return;
+        };
+        if now < *next_attempt {
+            return;
+        }
+        let connected = UnixStream::connect(&self.options.socket_path)
+            .and_then(|stream| LineConnection::register(stream, &self.registry, PLAYER));
+        match connected {
+            Ok(connection) => {
+                log_debug!("Connected to VLC socket.");
+                let queued = std::mem::take(queued);
+                self.connection = VlcConnection::Connected {
+                    connection,
+                    next_get_time: now + POLL_INTERVAL,
+                };
+                for request in queued {
+                    self.send(request);
+                }
+            }
+            Err(e) if *attempts < CONNECT_ATTEMPTS => {
+                log_debug!("VLC socket not ready yet: {}", e);
+                *attempts += 1;
+                *next_attempt = now + CONNECT_RETRY_INTERVAL;
+            }
+            Err(_) => {
+                log_error!("Failed to connect to VLC socket after ~10s worth of attempts.");
+                self.lose_connection();
+            }
+        }
+    }
+
+    // Asks where VLC is, if it's time, sends whatever's queued, and reads the answers.
+    fn talk(&mut self, now: Instant) -> Vec<PlaybackEvent> {
+        let VlcConnection::Connected { next_get_time, .. } = &mut self.connection else {
+            return Vec::new();
+        };
+        if now >= *next_get_time {
+            *next_get_time = now + POLL_INTERVAL;
+            self.send(VlcRequest::GetTime);
+        }
+        let VlcConnection::Connected { connection, .. } = &mut self.connection else {
+            return Vec::new();
+        };
+
+        let result = connection.flush().and_then(|()| connection.read_lines());
+        let closed = connection.is_closed();
+        let mut events = Vec::new();
+        match result {
+            Ok(lines) if !closed => {
+                for line in lines {
+                    events.extend(self.handle_line(&line));
+                }
+                return events;
+            }
+            Ok(_) => log_debug!("VLC socket closed."),
+            Err(e) => log_error!("Failed to talk to VLC: {}", e),
+        }
+        if let VlcConnection::Connected { connection, .. } =
+            std::mem::replace(&mut self.connection, VlcConnection::None)
+        {
+            connection.close(&self.registry);
+        }
+        self.lose_connection();
+        events
+    }
+
+    // Whether VLC's still there or not, it's done talking to us. Central hears about it once it's gone.
+    fn lose_connection(&mut self) {
+        self.connection = VlcConnection::Lost;
+        self.reaper
+            .reap(self.generation, self.child.take(), self.started_at);
+    }
+
+    // Unlike mpv's IPC, rc only ever answers, so the only thing it'll say is where it is, when we ask.
+    fn handle_line(&mut self, line: &str) -> Vec<PlaybackEvent> {
+        log_trace!("[VLC] < {}", line);
+        if is_vlc_stop(line) && self.is_playing {
+            self.is_playing = false;
+            return vec![PlaybackEvent::FileEnded];
+        }
+        match parse_vlc_time(line) {
+            Some(position) if self.is_loading => {
+                if position > 0.0 {
+                    self.is_loading = false;
+                    self.is_playing = true;
+                    vec![PlaybackEvent::FileLoaded, PlaybackEvent::PlaybackRestart]
+                } else {
+                    Vec::new()
+                }
+            }
+            Some(position) => vec![PlaybackEvent::Position(position)],
+            None => Vec::new(),
+        }
+    }
+}
+
+impl PlaybackBackend for VlcBackend {
+    fn start(&mut self) {
+        self.ensure_running();
+    }
+
+    fn is_running(&self) -> bool {
+        matches!(
+            self.connection,
+            VlcConnection::Connecting { .. } | VlcConnection::Connected { .. }
+        )
+    }
+
+    fn load(&mut self, url: &str) {
+        self.ensure_running();
+        self.send(VlcRequest::Add(url.to_string()));
+        self.paused = false;
+    }
+
+    fn seek(&mut self, position: f64) {
+        self.send(VlcRequest::Seek(position));
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        if paused == self.paused {
+            return;
+        }
+        self.paused = paused;
+        self.send(if paused {
+            VlcRequest::Pause
+        } else {
+            VlcRequest::Play
+        });
+    }
+
+    fn stop(&mut self) {
+        self.send(VlcRequest::Stop);
+    }
+
+    fn show_text(&mut self, text: &str) {
+        log_debug!("(VLC can't show this) {}", text);
+    }
+
+    fn poll(&mut self, now: Instant) -> Vec<PlaybackEvent> {
+        self.try_connect(now);
+        let mut events = self.talk(now);
+        for (generation, exit) in self.reaper.poll(now) {
+            // Reports about an older instance would only confuse Central.
+            if generation == self.generation {
+                self.exit = Some(exit);
+                events.push(PlaybackEvent::Disconnected(generation));
+            }
+        }
+        events
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        earliest([
+            match &self.connection {
+                VlcConnection::Connecting { next_attempt, .. } => Some(*next_attempt),
+                VlcConnection::Connected { next_get_time, .. } => Some(*next_get_time),
+                VlcConnection::None | VlcConnection::Lost => None,
+            },
+            self.reaper.next_deadline(),
+        ])
+    }
+
+    fn shutdown(&mut self) {
+        let quit = self.options.quit_on_exit;
+        if let VlcConnection::Connected { mut connection, .. } =
+            std::mem::replace(&mut self.connection, VlcConnection::None)
+        {
+            if quit {
+                log_info!("Closing VLC.");
+                connection.queue(&String::from(VlcRequest::Quit));
+            }
+            if let Err(e) = connection.flush_all(QUIT_TIMEOUT) {
+                log_error!("Failed to send the last commands to VLC: {}", e);
+            }
+            connection.close(&self.registry);
+        }
+        if quit {
+            if let Some(child) = self.child.take() {
+                self.reaper
+                    .reap(self.generation, Some(child), self.started_at);
+            }
+            remove_socket(&self.options.socket_path);
+        }
+        // Along with any that were already on their way out.
+        self.reaper.wait();
+    }
+
+    fn handle_disconnect(&mut self, generation: u64) -> Option<PlayerExit> {
+        if generation != self.generation || !matches!(self.connection, VlcConnection::Lost) {
+            return None;
+        }
+        self.connection = VlcConnection::None;
+        log_debug!("VLC (instance {}) is gone.", generation);
+        self.exit.take()
+    }
+}
@@ -0,0 +1,30 @@
+#[cfg(test)]
+mod vlc_rc {
+    use crate::vlc_commander::{is_vlc_stop, parse_vlc_time, VlcRequest};
+
+    #[test]
+    fn it_parses_get_time_replies() {
+        assert_eq!(parse_vlc_time("93\r\n"), Some(93.0));
+        assert_eq!(parse_vlc_time("> 93\n"), Some(93.0));
+        assert_eq!(parse_vlc_time("> > 0\n"), Some(0.0));
+        assert_eq!(
+            parse_vlc_time("status change: ( play state: 3 ): Play\n"),
+            None
+        );
+        assert_eq!(parse_vlc_time("> \n"), None);
+    }
+
+    #[test]
+    fn it_notices_when_playback_stops() {
+        assert!(is_vlc_stop("status change: ( stop state: 5 )\r\n"));
+        assert!(is_vlc_stop("> status change: ( stop state: 0 )\n"));
+        assert!(!is_vlc_stop("status change: ( play state: 3 ): Play\n"));
+        assert!(!is_vlc_stop("> 93\n"));
+    }
+
+    #[test]
+    fn it_seeks_to_whole_seconds() {
+        assert_eq!(String::from(VlcRequest::Seek(93.6)), "seek 94\n");
+        assert_eq!(String::from(VlcRequest::Seek(-0.4)), "seek 0\n");
+    }
+}
@@ -1,114 +1,288 @@
 use lazy_regex::lazy_regex;
 use lazy_regex::Lazy;
 use std::{
+    collections::HashMap,
     fs::{self, File},
-    io::{BufRead, BufReader, Result},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
-    sync::mpsc,
+    sync::{mpsc::Receiver, Arc},
 };
 
 use chrono::{DateTime, Local, TimeZone};
+use memchr::{memmem::Finder, memrchr};
+use mio::Waker;
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
-use regex::Regex;
+use regex::{Regex, RegexSet};
+
+use crate::{central::CentralCommand, event_loop::loop_channel, log_debug, log_warn};
+
+// One kind of ingame video player, and how to recognize the lines it logs.
+#[derive(Clone)]
+pub(crate) struct PlayerDefinition {
+    pub(crate) name: String,
+    // A literal that appears in every URL and seek line this player logs. The vast majority of lines in the log don't
+    // contain it, so we can skip those without running any regex at all.
+    pub(crate) needle: String,
+    // Must capture `timestamp`, `player_name` and `url`.
+    pub(crate) url_regex: Regex,
+    // Must capture `timestamp`, `player_name` and `new_offset`.
+    pub(crate) seek_regex: Regex,
+}
 
-use crate::{log_debug, log_error};
+pub(crate) fn builtin_player_definitions() -> Vec<PlayerDefinition> {
+    vec![PlayerDefinition {
+        name: "ProTV".to_string(),
+        needle: "TVManager".to_string(),
+        url_regex: URL_REGEX.clone(),
+        seek_regex: SEEK_REGEX.clone(),
+    }]
+}
 
-fn try_match_seek_line(line: &String, player_name_regex: &Option<Regex>) -> Option<FoundSeek> {
-    if let Some(captures) = &SEEK_REGEX.captures(&line) {
-        let timestamp = captures.name("timestamp").unwrap().as_str();
-        let seek_offset = captures.name("new_offset").unwrap().as_str();
-        let player_name = captures.name("player_name").unwrap().as_str();
+#[derive(Clone)]
+struct CompiledPlayerDefinition {
+    definition: PlayerDefinition,
+    finder: Finder<'static>,
+    // The URL and seek regexes, in that order. Once the needle matches, this tells us which one is worth running
+    // captures for, in a single pass over the line.
+    regex_set: RegexSet,
+}
+
+// Turns log lines into events, for all known player definitions.
+#[derive(Clone)]
+pub(crate) struct LogMatcher {
+    players: Vec<CompiledPlayerDefinition>,
+    world_change_finder: Finder<'static>,
+    // Players have to match all of these to be followed.
+    player_name_regexes: Vec<Regex>,
+}
+
+impl LogMatcher {
+    pub(crate) fn new(
+        player_definitions: Vec<PlayerDefinition>,
+        player_name_regex: Option<Regex>,
+    ) -> Self {
+        log_debug!(
+            "Matching log lines for these players: {}",
+            player_definitions
+                .iter()
+                .map(|definition| definition.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let players = player_definitions
+            .into_iter()
+            .map(|definition| CompiledPlayerDefinition {
+                finder: Finder::new(definition.needle.as_bytes()).into_owned(),
+                regex_set: RegexSet::new([
+                    definition.url_regex.as_str(),
+                    definition.seek_regex.as_str(),
+                ])
+                .expect("Regexes that compiled on their own should compile as a set"),
+                definition,
+            })
+            .collect();
+
+        Self {
+            players,
+            world_change_finder: Finder::new(WORLD_CHANGE_NEEDLE).into_owned(),
+            player_name_regexes: player_name_regex.into_iter().collect(),
+        }
+    }
 
-        if let Some(player_name_regex) = player_name_regex {
-            if !player_name_regex.is_match(player_name) {
+    pub(crate) fn match_line(&self, line: &str) -> Option<VrcLogWatcherEvent> {
+        let bytes = line.as_bytes();
+
+        // The needle is on both the join line and the room name line that follows it.
+        if self.world_change_finder.find(bytes).is_some() {
+            if let Some(found_world_change) = try_match_world_change_line(line) {
+                return Some(VrcLogWatcherEvent::FoundWorldChange(found_world_change));
+            }
+            if let Some(found_world_name) = try_match_world_name_line(line) {
+                return Some(VrcLogWatcherEvent::FoundWorldName(found_world_name));
+            }
+        }
+
+        for player in &self.players {
+            if player.finder.find(bytes).is_none() {
+                continue;
+            }
+
+            // A line this definition matches, but can't make sense of or doesn't follow, may still be one a later
+            // definition follows.
+            let matches = player.regex_set.matches(line);
+            if matches.matched(0) {
+                if let Some(found_url) = self.try_match_url_line(&player.definition.url_regex, line)
+                {
+                    return Some(VrcLogWatcherEvent::FoundUrl(found_url));
+                }
+            }
+            if matches.matched(1) {
+                if let Some(found_seek) =
+                    self.try_match_seek_line(&player.definition.seek_regex, line)
+                {
+                    return Some(VrcLogWatcherEvent::FoundSeek(found_seek));
+                }
+            }
+        }
+
+        None
+    }
+
+    // The same, but only following players that also match `player_name_regex`, if given.
+    pub(crate) fn with_player_name_regex(&self, player_name_regex: Option<Regex>) -> Self {
+        let mut matcher = self.clone();
+        matcher.player_name_regexes.extend(player_name_regex);
+        matcher
+    }
+
+    fn is_followed_player_name(&self, player_name: &str) -> bool {
+        self.player_name_regexes
+            .iter()
+            .all(|player_name_regex| player_name_regex.is_match(player_name))
+    }
+
+    fn try_match_seek_line(&self, seek_regex: &Regex, line: &str) -> Option<FoundSeek> {
+        if let Some(captures) = &seek_regex.captures(line) {
+            let timestamp = captures.name("timestamp").unwrap().as_str();
+            let seek_offset = captures.name("new_offset").unwrap().as_str();
+            let player_name = captures.name("player_name").unwrap().as_str();
+
+            if !self.is_followed_player_name(player_name) {
                 log_debug!(
                     "Skipping seek line because it doesn't match the player name regex: {:#?}",
                     player_name
                 );
                 return None;
             }
-        }
 
-        log_debug!("Found seek line: {:#?}", line);
+            log_debug!("Found seek line: {:#?}", line);
+
+            let timestamp = parse_captured_timestamp(timestamp, line)?;
+            // also, parse the seek offset as a floating point
+            let Some(seek_offset) = seek_offset
+                .parse::<f64>()
+                .ok()
+                .filter(|seek_offset| seek_offset.is_finite())
+            else {
+                log_warn!(
+                    "Skipping seek line, since {:?} isn't an offset. Does the player's seek_regex capture too much? {:#?}",
+                    seek_offset,
+                    line
+                );
+                return None;
+            };
+            return Some(FoundSeek {
+                timestamp,
+                seek_offset,
+                player_name: player_name.to_string(),
+            });
+        }
 
-        let timestamp = parse_timestamp(timestamp);
-        // also, parse the seek offset as a floating point
-        let seek_offset = seek_offset
-            .parse::<f64>()
-            .expect("Failed to parse seek offset as f64");
-        return Some(FoundSeek {
-            timestamp,
-            seek_offset,
-        });
+        None
     }
 
-    None
-}
+    fn try_match_url_line(&self, url_regex: &Regex, line: &str) -> Option<FoundUrl> {
+        if let Some(captures) = &url_regex.captures(line) {
+            let timestamp = captures.name("timestamp").unwrap().as_str();
+            let url = captures.name("url").unwrap().as_str();
+            let player_name = captures.name("player_name").unwrap().as_str();
 
-fn parse_timestamp(timestamp: &str) -> DateTime<Local> {
-    // timestamp is of the form:
-    // 2024.04.22 17:55:53
-    // parse it as local time:
-    let timestamp = chrono::naive::NaiveDateTime::parse_from_str(&timestamp, "%Y.%m.%d %H:%M:%S")
-        .expect("Failed to parse timestamp");
-    let timestamp = chrono::Local
-        .from_local_datetime(&timestamp)
-        .earliest()
-        .expect("Failed to convert timestamp to local time");
-    timestamp
-}
-
-fn try_match_url_line(
-    line: &String,
-    line_number: u64,
-    player_name_regex: &Option<Regex>,
-) -> Option<FoundUrl> {
-    if let Some(captures) = &URL_REGEX.captures(&line) {
-        let timestamp = captures.name("timestamp").unwrap().as_str();
-        let url = captures.name("url").unwrap().as_str();
-        let timestamp = parse_timestamp(timestamp);
-        let player_name = captures.name("player_name").unwrap().as_str();
-
-        if let Some(player_name_regex) = player_name_regex {
-            if !player_name_regex.is_match(player_name) {
+            if !self.is_followed_player_name(player_name) {
                 log_debug!(
                     "Skipping URL line because it doesn't match the player name regex: {:#?}",
                     player_name
                 );
                 return None;
             }
+
+            log_debug!("Found URL line: {:#?}", line);
+
+            let timestamp = parse_captured_timestamp(timestamp, line)?;
+            return Some(FoundUrl {
+                timestamp,
+                url: url.to_string(),
+                player_name: player_name.to_string(),
+            });
         }
 
-        log_debug!("Found URL line: {:#?}", line);
+        None
+    }
+}
+
+pub(crate) fn parse_timestamp(timestamp: &str) -> Option<DateTime<Local>> {
+    // timestamp is of the form:
+    // 2024.04.22 17:55:53
+    // parse it as local time:
+    let timestamp =
+        chrono::naive::NaiveDateTime::parse_from_str(timestamp, "%Y.%m.%d %H:%M:%S").ok()?;
+    // None if the clocks went forward past it
+    chrono::Local.from_local_datetime(&timestamp).earliest()
+}
 
-        return Some(FoundUrl {
+// Whatever a regex captured as the timestamp, which for the ones in players.toml may not actually be one. Rather than
+// give up on the log over it, the line is skipped.
+fn parse_captured_timestamp(timestamp: &str, line: &str) -> Option<DateTime<Local>> {
+    let parsed = parse_timestamp(timestamp);
+    if parsed.is_none() {
+        log_warn!(
+            "Skipping line, since {:?} isn't a timestamp. Does the regex capture too much, or too little? {:#?}",
             timestamp,
-            url: url.to_string(),
-            found_url_on_line: line_number,
+            line
+        );
+    }
+    parsed
+}
+
+// Every log entry starts with a timestamp. Lines that continue a multi-line entry don't have one.
+pub(crate) fn try_parse_line_timestamp(line: &str) -> Option<DateTime<Local>> {
+    parse_timestamp(line.get(..19)?)
+}
+
+fn try_match_world_change_line(line: &str) -> Option<FoundWorldChange> {
+    if let Some(captures) = &WORLD_CHANGE_REGEX.captures(line) {
+        let timestamp = captures.name("timestamp").unwrap().as_str();
+        let world_id = captures.name("world_id").unwrap().as_str();
+
+        log_debug!("Found world change line: {:#?}", line);
+
+        return Some(FoundWorldChange {
+            timestamp: parse_captured_timestamp(timestamp, line)?,
+            world_id: world_id.to_string(),
         });
     }
 
     None
 }
 
-fn get_latest_vrc_log_file() -> Option<PathBuf> {
-    let log_dir = get_vrc_log_file_dir();
+fn try_match_world_name_line(line: &str) -> Option<FoundWorldName> {
+    let captures = WORLD_NAME_REGEX.captures(line)?;
+    let timestamp = captures.name("timestamp").unwrap().as_str();
+    let world_name = captures.name("world_name").unwrap().as_str();
+
+    log_debug!("Found world name line: {:#?}", line);
+
+    Some(FoundWorldName {
+        timestamp: parse_captured_timestamp(timestamp, line)?,
+        world_name: world_name.trim_end().to_string(),
+    })
+}
+
+pub(crate) fn get_latest_vrc_log_file(log_dir: &Path) -> Option<PathBuf> {
     let mut latest_log = None;
 
     // read dir
-    if let Ok(entries) = fs::read_dir(&log_dir) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                if let Some(file_name) = entry.file_name().to_str() {
-                    // for all log files,
-                    if file_name.starts_with("output_log_") && file_name.ends_with(".txt") {
-                        let file_path = entry.path();
-                        if latest_log
-                            .as_ref()
-                            .map_or(true, |log| file_path > Path::new(log))
-                        {
-                            latest_log = Some(file_path);
-                        }
+    if let Ok(entries) = fs::read_dir(log_dir) {
+        for entry in entries.flatten() {
+            if let Some(file_name) = entry.file_name().to_str() {
+                // for all log files,
+                if file_name.starts_with("output_log_") && file_name.ends_with(".txt") {
+                    let file_path = entry.path();
+                    if latest_log
+                        .as_ref()
+                        .is_none_or(|log| file_path > Path::new(log))
+                    {
+                        latest_log = Some(file_path);
                     }
                 }
             }
@@ -118,9 +292,11 @@ fn get_latest_vrc_log_file() -> Option<PathBuf> {
     latest_log
 }
 
-fn get_vrc_log_file_dir() -> String {
-    let log_dir = format!("{}/.steam/steam/steamapps/compatdata/438100/pfx/drive_c/users/steamuser/AppData/LocalLow/VRChat/VRChat", std::env::var("HOME").unwrap_or_default());
-    log_dir
+// Where VRChat writes its logs, when run through Proton from the default Steam library.
+pub(crate) fn default_vrc_log_dir() -> PathBuf {
+    PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(
+        ".steam/steam/steamapps/compatdata/438100/pfx/drive_c/users/steamuser/AppData/LocalLow/VRChat/VRChat",
+    )
 }
 
 /*
@@ -175,175 +351,269 @@ pub(crate) static SEEK_REGEX: Lazy<Regex> = lazy_regex!(
     r"^(?P<timestamp>[0-9.: ]+) Log +- +\[ATA? (?:INFO|DEBUG|\|)[ \t]+TVManager(?:V2)? \((?P<player_name>.*)\)\] (((Sync enforcement(?: requested)?|Paused drift threshold exceeded). Updating to)|Jumping \[.*\] to timestamp:) (?P<new_offset>[0-9.]+)$"
 );
 
-fn tail_file<FCallback>(
-    path: &PathBuf,
-    start_after_line: u64,
-    mut callback: FCallback,
-) -> notify::Result<()>
-where
-    FCallback: FnMut(String, u64),
-{
-    let (tx, rx) = mpsc::channel();
-    let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
-    watcher.watch(&path, RecursiveMode::NonRecursive)?;
-
-    let f = File::open(path)?;
-    // skip ahead initially
-    skip_n_lines(&f, start_after_line)?;
-    // line numbers are 1-based. if I skip 3 lines, I am now at line 4.
-
-    // view the file as lines, and keep track of the line number
-    let lines = BufReader::new(&f).lines().enumerate();
-
-    // read the rest of the file as it exists, calling the callback for each line
-    for (i, line) in lines {
-        let current_line_num = (i as u64) + start_after_line;
-        let line = line.unwrap();
-        callback(line, current_line_num);
+// Reads a log file incrementally, handing out only complete lines.
+// `offset` always points just past the last newline we've handed out, so it can be used as a checkpoint: whoever opens
+// the file next can seek straight to it instead of re-reading and counting lines.
+pub(crate) struct LogTail {
+    path: PathBuf,
+    file: File,
+    // Identifies which file we have open, so we can tell if something else has been put in its place.
+    dev_and_ino: (u64, u64),
+    pub(crate) offset: u64,
+    // Bytes after `offset` that have been read, but aren't terminated by a newline yet. VRChat may flush a line halfway,
+    // so we hold on to these until the rest of the line shows up.
+    partial_line: Vec<u8>,
+}
+
+impl LogTail {
+    pub(crate) fn open(path: &Path, start_offset: u64) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        // The file may have shrunk since the offset was recorded. Don't seek past the end and wait forever.
+        let start_offset = if metadata.len() < start_offset {
+            log_warn!(
+                "Log file is smaller than the requested start offset ({} < {}), so reading it from the start.",
+                metadata.len(),
+                start_offset
+            );
+            0
+        } else {
+            start_offset
+        };
+        file.seek(SeekFrom::Start(start_offset))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            dev_and_ino: (metadata.dev(), metadata.ino()),
+            offset: start_offset,
+            partial_line: Vec::new(),
+        })
     }
 
-    // now, we'll keep watching the file for changes
-    for res in rx {
-        match res {
-            Ok(_) => {
-                // log_debug!("File changed with event: {:?}", event);
-
-                // create a new BufReader for the file, because we're in the watcher loop so we can't move the old one
-                let lines = BufReader::new(&f).lines().enumerate();
-                for (current_line_num, line) in lines {
-                    let current_line_num = current_line_num.try_into().unwrap();
-                    let line = line.unwrap();
-                    callback(line, current_line_num);
-                }
+    // Checks whether the log file was truncated or replaced since we last read it, and if so, starts over from the
+    // beginning of whatever is there now. Anything we had buffered belonged to the old contents, so it's discarded.
+    fn reopen_if_rotated(&mut self) -> io::Result<()> {
+        let replaced = match fs::metadata(&self.path) {
+            Ok(metadata) => (metadata.dev(), metadata.ino()) != self.dev_and_ino,
+            // The file is gone for now (mid-rename?). Keep reading the handle we have; we'll check again next time.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+        if replaced {
+            log_debug!("Log file was replaced, so reopening it: {:#?}", self.path);
+            *self = Self::open(&self.path, 0)?;
+            return Ok(());
+        }
+
+        let read_so_far = self.offset + self.partial_line.len() as u64;
+        let current_len = self.file.metadata()?.len();
+        if current_len < read_so_far {
+            log_debug!(
+                "Log file was truncated ({} < {}), so reading it from the start.",
+                current_len,
+                read_so_far
+            );
+            self.file.seek(SeekFrom::Start(0))?;
+            self.offset = 0;
+            self.partial_line.clear();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_new_lines<F>(&mut self, mut on_line: F) -> io::Result<()>
+    where
+        F: FnMut(&str, u64),
+    {
+        self.reopen_if_rotated()?;
+
+        let mut reader = BufReader::new(&self.file);
+        loop {
+            let bytes_read = reader.read_until(b'\n', &mut self.partial_line)?;
+            if bytes_read == 0 {
+                // EOF. Whatever is left in partial_line stays there until the next call.
+                break;
             }
-            Err(err) => {
-                log_error!("Error: {:?}", err);
-                std::process::exit(1);
+            if !self.partial_line.ends_with(b"\n") {
+                continue;
             }
+
+            let line_offset = self.offset;
+            self.offset += self.partial_line.len() as u64;
+            let line = String::from_utf8_lossy(&self.partial_line);
+            on_line(line.trim_end_matches(['\r', '\n']), line_offset);
+            self.partial_line.clear();
         }
+        Ok(())
     }
+}
+
+// Printed by VRChat itself whenever the local user joins an instance, regardless of which video player the world uses.
+// 2024.06.06 17:20:01 Log        -  [Behaviour] Joining wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~private(usr_00000000-0000-0000-0000-000000000000)~region(us)
+const WORLD_CHANGE_NEEDLE: &str = "[Behaviour] Joining ";
+pub(crate) static WORLD_CHANGE_REGEX: Lazy<Regex> = lazy_regex!(
+    r"^(?P<timestamp>[0-9.: ]+) Log +- +\[Behaviour\] Joining (?P<world_id>wrld_[^:\s]+)"
+);
+
+// Follows the join line, once VRChat knows what the world is called.
+// 2024.06.06 17:20:02 Log        -  [Behaviour] Joining or Creating Room: Movie & Chill
+pub(crate) static WORLD_NAME_REGEX: Lazy<Regex> = lazy_regex!(
+    r"^(?P<timestamp>[0-9.: ]+) Log +- +\[Behaviour\] Joining or Creating Room: (?P<world_name>.+)"
+);
 
-    Ok(())
+// Yields the complete lines of a file from last to first, reading it in blocks from the end.
+// Only lines before `end_offset` are yielded: a trailing line without a newline is still being written, and is left for
+// the watcher to pick up.
+struct ReverseLines {
+    file: File,
+    // File offset of buf[0].
+    buf_start: u64,
+    // Everything between buf_start and the end of the next line to be yielded, excluding its newline.
+    buf: Vec<u8>,
+    end_offset: u64,
 }
 
-fn skip_n_lines(file: &File, n: u64) -> Result<()> {
-    let mut lines = BufReader::new(file).lines();
-    for i in 0..n {
-        let line = lines.next();
-        if line.is_none() {
-            panic!("File is smaller than the given line number. {} < {}", i, n);
+const REVERSE_BLOCK_SIZE: u64 = 256 * 1024;
+
+impl ReverseLines {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reverse_lines = Self {
+            file,
+            buf_start: len,
+            buf: Vec::new(),
+            end_offset: 0,
+        };
+
+        // find the last newline, and drop whatever comes after it
+        while memrchr(b'\n', &reverse_lines.buf).is_none() && reverse_lines.buf_start > 0 {
+            reverse_lines.read_previous_block()?;
         }
+        match memrchr(b'\n', &reverse_lines.buf) {
+            Some(newline) => {
+                reverse_lines.end_offset = reverse_lines.buf_start + newline as u64 + 1;
+                reverse_lines.buf.truncate(newline);
+            }
+            None => {
+                // not a single complete line in the file
+                reverse_lines.buf.clear();
+            }
+        }
+        Ok(reverse_lines)
     }
-    Ok(())
-}
 
-fn watch_file<FFoundUrl, FFoundSeek>(
-    log_path: &PathBuf,
-    start_after_line: u64,
-    player_name_regex: &Option<Regex>,
-    mut on_found_url: FFoundUrl,
-    mut on_found_seek: FFoundSeek,
-) where
-    FFoundUrl: FnMut(FoundUrl),
-    FFoundSeek: FnMut(FoundSeek),
-{
-    let player_name_regex = player_name_regex.clone();
-    tail_file(log_path, start_after_line, |line, line_number| {
-        if let Some(found_url) = try_match_url_line(&line, line_number, &player_name_regex) {
-            on_found_url(found_url);
-        }
-        if let Some(found_seek) = try_match_seek_line(&line, &player_name_regex) {
-            on_found_seek(found_seek);
+    fn read_previous_block(&mut self) -> io::Result<()> {
+        let block_start = self.buf_start.saturating_sub(REVERSE_BLOCK_SIZE);
+        let mut block = vec![0; (self.buf_start - block_start) as usize];
+        self.file.seek(SeekFrom::Start(block_start))?;
+        self.file.read_exact(&mut block)?;
+        block.extend_from_slice(&self.buf);
+        self.buf = block;
+        self.buf_start = block_start;
+        Ok(())
+    }
+
+    fn next_line(&mut self) -> io::Result<Option<(String, u64)>> {
+        loop {
+            if let Some(newline) = memrchr(b'\n', &self.buf) {
+                let line_offset = self.buf_start + newline as u64 + 1;
+                let line = String::from_utf8_lossy(&self.buf[newline + 1..])
+                    .trim_end_matches('\r')
+                    .to_string();
+                self.buf.truncate(newline);
+                return Ok(Some((line, line_offset)));
+            }
+
+            if self.buf_start == 0 {
+                // the first line of the file has no newline in front of it
+                if self.end_offset == 0 {
+                    return Ok(None);
+                }
+                let line = String::from_utf8_lossy(&self.buf)
+                    .trim_end_matches('\r')
+                    .to_string();
+                self.end_offset = 0;
+                self.buf.clear();
+                return Ok(Some((line, 0)));
+            }
+
+            self.read_previous_block()?;
         }
-    })
-    .expect("Failed to tail file.");
+    }
 }
 
 pub(crate) struct VrcLogReader {
     log_path: PathBuf,
-    lines_read_initially: Option<u64>,
-
-    player_name_regex: Option<Regex>,
+    matcher: LogMatcher,
 }
 
 impl VrcLogReader {
-    fn new(path: PathBuf, player_name_regex: Option<Regex>) -> Self {
+    pub(crate) fn new(path: PathBuf, matcher: LogMatcher) -> Self {
         Self {
             log_path: path,
-            lines_read_initially: None,
-
-            player_name_regex,
+            matcher,
         }
     }
 
-    pub(crate) fn from_latest(player_name_regex: &Option<Regex>) -> Self {
-        let log_path = get_latest_vrc_log_file().expect("No VRC log files found.");
-        Self::new(log_path, player_name_regex.clone())
-    }
-
+    // Logs can get very large, but we only care about what happened since the last world join. So rather than
+    // streaming the whole thing forwards, we read it backwards from the end, in a single pass, and stop as soon as we
+    // know what's playing.
     pub(crate) fn get_latest_url_and_seek(&mut self) -> UrlAndSeekResult {
-        if let Some(found_url) = self.find_last_url() {
-            if let Some(found_seek) = self.find_last_seek(found_url.found_url_on_line) {
-                return UrlAndSeekResult::UrlAndSeek(
-                    found_url,
-                    found_seek,
-                    self.lines_read_initially.unwrap(),
-                );
-            }
-            return UrlAndSeekResult::Url(found_url, self.lines_read_initially.unwrap());
-        }
-
-        UrlAndSeekResult::Nothing(self.lines_read_initially.unwrap())
-    }
-
-    fn find_last_url(&mut self) -> Option<FoundUrl> {
-        let log_path = &self.log_path;
-        let mut last_url: Option<FoundUrl> = None;
-        log_debug!("Log file: {:#?}", log_path);
-        // read log file and look for the last line that matches the regex
-        // we must stream the log file as it can be very large
-        let file = File::open(log_path).expect("Expected log file to exist.");
-        // we should go through the lines forwards, because even though we want the last video url and could exit early if we find it,
-        // it's likely that all or most URLs will be toward the beginning of the file
-        let lines = BufReader::new(file).lines();
-        let mut line_count: u64 = 0;
-        for line in lines {
-            if let Ok(line) = line {
-                line_count += 1;
-                if line_count % 100000 == 0 {
-                    log_debug!("Processed {} lines.", line_count);
+        log_debug!("Log file: {:#?}", self.log_path);
+        let mut lines = ReverseLines::open(&self.log_path).expect("Expected log file to exist.");
+        // the watcher picks up from here
+        let bytes_read_initially = lines.end_offset;
+
+        // Seeks are logged after the URL they apply to, so we'll see them first. Hold on to the most recent one for
+        // each player, until we find out which player's URL is the latest.
+        let mut last_seek_by_player: HashMap<String, FoundSeek> = HashMap::new();
+
+        while let Some((line, _)) = lines.next_line().expect("Failed to read log file.") {
+            match self.matcher.match_line(&line) {
+                Some(VrcLogWatcherEvent::FoundWorldChange(_)) => {
+                    // anything before this was playing in a world we're no longer in
+                    break;
                 }
-
-                if let Some(found_url) =
-                    try_match_url_line(&line, line_count, &self.player_name_regex)
-                {
-                    last_url = Some(found_url);
+                Some(VrcLogWatcherEvent::FoundUrl(found_url)) => {
+                    return match last_seek_by_player.remove(&found_url.player_name) {
+                        Some(found_seek) => UrlAndSeekResult::UrlAndSeek(
+                            found_url,
+                            found_seek,
+                            bytes_read_initially,
+                        ),
+                        None => UrlAndSeekResult::Url(found_url, bytes_read_initially),
+                    };
+                }
+                Some(VrcLogWatcherEvent::FoundSeek(found_seek)) => {
+                    last_seek_by_player
+                        .entry(found_seek.player_name.clone())
+                        .or_insert(found_seek);
                 }
+                Some(VrcLogWatcherEvent::FoundWorldName(_)) | None => {}
             }
         }
-        self.lines_read_initially = Some(line_count);
-        last_url
+
+        UrlAndSeekResult::Nothing(bytes_read_initially)
     }
 
-    fn find_last_seek(&mut self, not_before_this_line: u64) -> Option<FoundSeek> {
-        let log_path = &self.log_path;
-        let mut last_seek: Option<FoundSeek> = None;
-        let file = File::open(log_path).expect("Expected log file to exist.");
-        let lines = BufReader::new(file).lines();
-        let mut line_count: u64 = 0;
-        for line in lines {
-            if let Ok(line) = line {
-                line_count += 1;
-                if line_count < not_before_this_line {
-                    continue;
+    // Which world we're in, and what it's called, if VRChat got around to logging that. Like the above, this reads
+    // backwards, and stops at the last join.
+    pub(crate) fn get_current_world(&mut self) -> Option<(FoundWorldChange, Option<String>)> {
+        let mut lines = ReverseLines::open(&self.log_path).expect("Expected log file to exist.");
+        // the name comes after the join, so we'll see it first
+        let mut world_name = None;
+        while let Some((line, _)) = lines.next_line().expect("Failed to read log file.") {
+            match self.matcher.match_line(&line) {
+                Some(VrcLogWatcherEvent::FoundWorldChange(found_world_change)) => {
+                    return Some((found_world_change, world_name));
                 }
-
-                if let Some(found_seek) = try_match_seek_line(&line, &self.player_name_regex) {
-                    last_seek = Some(found_seek);
+                Some(VrcLogWatcherEvent::FoundWorldName(found_world_name)) => {
+                    world_name.get_or_insert(found_world_name.world_name);
                 }
+                _ => {}
             }
         }
-        last_seek
+        None
     }
 }
 
@@ -353,55 +623,128 @@ pub(crate) enum UrlAndSeekResult {
     UrlAndSeek(FoundUrl, FoundSeek, u64),
 }
 
+// Follows the log as VRChat writes it. Rather than having a thread of its own, it's polled by the event loop, which the
+// file watcher wakes up whenever anything in the log's directory changes.
 pub(crate) struct VrcLogWatcher {
     log_path: PathBuf,
-    player_name_regex: Option<Regex>,
+    matcher: LogMatcher,
+    tail: LogTail,
+    // Has to be kept around, or it stops watching.
+    _watcher: RecommendedWatcher,
+    changes: Receiver<notify::Result<notify::Event>>,
+    // Whether there might be something new to read. There is, to begin with, since whatever was written between the
+    // initial read and the watch starting hasn't been read yet.
+    changed: bool,
+}
+
+// The directory the log is in. A bare file name has an empty parent, which can't be watched.
+pub(crate) fn log_dir(log_path: &Path) -> &Path {
+    match log_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
 }
 
 impl VrcLogWatcher {
-    fn new(path: PathBuf, player_name_regex: Option<Regex>) -> Self {
-        Self {
-            log_path: path,
-            player_name_regex,
-        }
+    pub(crate) fn new(
+        log_path: PathBuf,
+        start_offset: u64,
+        matcher: LogMatcher,
+        waker: Arc<Waker>,
+    ) -> notify::Result<Self> {
+        let (tx, changes) = loop_channel(waker);
+        let mut watcher = RecommendedWatcher::new(
+            move |res| {
+                // If the event loop is gone, we're shutting down anyway.
+                let _ = tx.send(res);
+            },
+            Config::default(),
+        )?;
+        // Watch the directory rather than the file itself. If the file is deleted and recreated, a watch on the file
+        // would stay attached to the old inode and we'd never hear about the new one.
+        watcher.watch(log_dir(&log_path), RecursiveMode::NonRecursive)?;
+
+        // jump straight to where the initial read left off
+        let tail = LogTail::open(&log_path, start_offset)?;
+        Ok(Self {
+            log_path,
+            matcher,
+            tail,
+            _watcher: watcher,
+            changes,
+            changed: true,
+        })
     }
 
-    pub(crate) fn from_latest(player_name_regex: &Option<Regex>) -> Self {
-        let log_path = get_latest_vrc_log_file().expect("No VRC log files found.");
-        Self::new(log_path, player_name_regex.clone())
+    // For when the config is reloaded. Takes over from the next line on.
+    pub(crate) fn set_matcher(&mut self, matcher: LogMatcher) {
+        self.matcher = matcher;
     }
 
-    pub(crate) fn watch_file<FFoundUrl, FFoundSeek>(
-        &mut self,
-        start_after_line: u64,
-        on_found_url: FFoundUrl,
-        on_found_seek: FFoundSeek,
-    ) where
-        FFoundUrl: FnMut(FoundUrl),
-        FFoundSeek: FnMut(FoundSeek),
-    {
-        watch_file(
-            &self.log_path,
-            start_after_line,
-            &self.player_name_regex,
-            on_found_url,
-            on_found_seek,
-        );
+    // Everything that's been logged since the last call, followed by a checkpoint if anything was read at all.
+    pub(crate) fn poll(&mut self) -> io::Result<Vec<CentralCommand>> {
+        for res in self.changes.try_iter() {
+            match res {
+                Ok(event) => {
+                    // otherwise, it's some other file in the log dir. The paths are the dir's as watched, joined with
+                    // the file name, so they needn't be spelled like log_path.
+                    if event
+                        .paths
+                        .iter()
+                        .any(|path| path.file_name() == self.log_path.file_name())
+                    {
+                        self.changed = true;
+                    }
+                }
+                Err(e) => log_warn!("Error watching the log file: {:?}", e),
+            }
+        }
+        if !self.changed {
+            return Ok(Vec::new());
+        }
+        self.changed = false;
+
+        let mut commands = Vec::new();
+        let offset_before = self.tail.offset;
+        let matcher = &self.matcher;
+        self.tail.read_new_lines(|line, _| {
+            if let Some(event) = matcher.match_line(line) {
+                commands.push(CentralCommand::VrcLogWatcherEvent(event));
+            }
+        })?;
+        if self.tail.offset != offset_before {
+            commands.push(CentralCommand::VrcLogWatcherCheckpoint(self.tail.offset));
+        }
+        Ok(commands)
     }
 }
 
 pub(crate) struct FoundSeek {
     pub(crate) timestamp: DateTime<Local>,
     pub(crate) seek_offset: f64,
+    pub(crate) player_name: String,
 }
 
 pub(crate) struct FoundUrl {
     pub(crate) timestamp: DateTime<Local>,
     pub(crate) url: String,
-    found_url_on_line: u64,
+    pub(crate) player_name: String,
+}
+
+pub(crate) struct FoundWorldChange {
+    pub(crate) timestamp: DateTime<Local>,
+    pub(crate) world_id: String,
+}
+
+pub(crate) struct FoundWorldName {
+    pub(crate) timestamp: DateTime<Local>,
+    pub(crate) world_name: String,
 }
 
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum VrcLogWatcherEvent {
     FoundUrl(FoundUrl),
     FoundSeek(FoundSeek),
+    FoundWorldChange(FoundWorldChange),
+    FoundWorldName(FoundWorldName),
 }
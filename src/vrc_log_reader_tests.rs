@@ -102,7 +102,7 @@ mod get_seek_regex {
     fn it_extracts_example_d() {
         let seek_regex = &crate::vrc_log_reader::SEEK_REGEX;
         let example_d = "2024.07.22 17:48:44 Log        -  [ATA | TVManagerV2 (ProTV Hangout (1))] Sync enforcement requested. Updating to 44.96499";
-        let example_captures = seek_regex.captures(&example_d).unwrap();
+        let example_captures = seek_regex.captures(example_d).unwrap();
         assert_eq!(
             example_captures.name("timestamp").unwrap().as_str(),
             "2024.07.22 17:48:44"
@@ -117,3 +117,541 @@ mod get_seek_regex {
         );
     }
 }
+
+#[cfg(test)]
+mod log_tail {
+    use std::{fs, fs::OpenOptions, io::Write, path::Path};
+
+    use crate::vrc_log_reader::LogTail;
+
+    fn append(path: &Path, contents: &str) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+    }
+
+    fn read_lines(tail: &mut LogTail) -> Vec<(String, u64)> {
+        let mut lines = Vec::new();
+        tail.read_new_lines(|line, offset| lines.push((line.to_string(), offset)))
+            .unwrap();
+        lines
+    }
+
+    #[test]
+    fn it_reports_byte_offsets_of_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output_log.txt");
+        append(&path, "first\nsecond\r\nthird\n");
+
+        let mut tail = LogTail::open(&path, 0).unwrap();
+        assert_eq!(
+            read_lines(&mut tail),
+            vec![
+                ("first".to_string(), 0),
+                ("second".to_string(), 6),
+                ("third".to_string(), 14)
+            ]
+        );
+        assert_eq!(tail.offset, 20);
+    }
+
+    #[test]
+    fn it_resumes_from_an_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output_log.txt");
+        append(&path, "first\nsecond\n");
+
+        let mut tail = LogTail::open(&path, 6).unwrap();
+        assert_eq!(read_lines(&mut tail), vec![("second".to_string(), 6)]);
+    }
+
+    #[test]
+    fn it_buffers_lines_written_in_fragments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output_log.txt");
+        append(&path, "first\nsec");
+
+        let mut tail = LogTail::open(&path, 0).unwrap();
+        assert_eq!(read_lines(&mut tail), vec![("first".to_string(), 0)]);
+        // the half-written line isn't part of the checkpoint yet
+        assert_eq!(tail.offset, 6);
+
+        append(&path, "o");
+        assert_eq!(read_lines(&mut tail), vec![]);
+
+        append(&path, "nd\nthi");
+        assert_eq!(read_lines(&mut tail), vec![("second".to_string(), 6)]);
+
+        append(&path, "rd\n");
+        assert_eq!(read_lines(&mut tail), vec![("third".to_string(), 13)]);
+        assert_eq!(tail.offset, 19);
+    }
+
+    #[test]
+    fn it_starts_over_when_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output_log.txt");
+        append(&path, "first line\nsecond line\npartial");
+
+        let mut tail = LogTail::open(&path, 0).unwrap();
+        assert_eq!(read_lines(&mut tail).len(), 2);
+
+        fs::write(&path, "new\n").unwrap();
+        assert_eq!(read_lines(&mut tail), vec![("new".to_string(), 0)]);
+    }
+
+    #[test]
+    fn it_starts_over_when_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output_log.txt");
+        append(&path, "old\nhalf of an old li");
+
+        let mut tail = LogTail::open(&path, 0).unwrap();
+        assert_eq!(read_lines(&mut tail), vec![("old".to_string(), 0)]);
+
+        // replace the file with a longer one, so the size check alone wouldn't notice
+        let replacement = dir.path().join("replacement.txt");
+        append(&replacement, "a much longer first line\nsecond\n");
+        fs::rename(&replacement, &path).unwrap();
+
+        assert_eq!(
+            read_lines(&mut tail),
+            vec![
+                ("a much longer first line".to_string(), 0),
+                ("second".to_string(), 25)
+            ]
+        );
+    }
+
+    #[test]
+    fn it_ignores_an_offset_past_the_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output_log.txt");
+        append(&path, "only\n");
+
+        let mut tail = LogTail::open(&path, 1000).unwrap();
+        assert_eq!(read_lines(&mut tail), vec![("only".to_string(), 0)]);
+    }
+}
+
+#[cfg(test)]
+mod log_dir {
+    use std::path::Path;
+
+    use crate::vrc_log_reader::log_dir;
+
+    #[test]
+    fn it_watches_the_dir_the_log_is_in() {
+        assert_eq!(
+            log_dir(Path::new("/tmp/VRChat/output_log.txt")),
+            Path::new("/tmp/VRChat")
+        );
+        assert_eq!(log_dir(Path::new("logs/output_log.txt")), Path::new("logs"));
+        // relative to the working directory
+        assert_eq!(log_dir(Path::new("output_log.txt")), Path::new("."));
+    }
+}
+
+#[cfg(test)]
+mod get_latest_url_and_seek {
+    use std::{fs, path::Path};
+
+    use crate::vrc_log_reader::{
+        builtin_player_definitions, LogMatcher, UrlAndSeekResult, VrcLogReader,
+    };
+
+    const JOIN: &str = "2024.07.22 17:40:00 Log        -  [Behaviour] Joining wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~region(us)\n";
+    const URL_A: &str = "2024.07.22 17:48:43 Log        -  [ATA | TVManagerV2 (ProTV Hangout (1))] [AVPro1080p] Now Playing: https://youtu.be/zL3wWykAKfs\n";
+    const SEEK_A: &str = "2024.07.22 17:48:44 Log        -  [ATA | TVManagerV2 (ProTV Hangout (1))] Sync enforcement requested. Updating to 44.96499\n";
+    const SEEK_B: &str = "2024.07.22 17:48:45 Log        -  [AT INFO   TVManager (Theatre 1 TVManager)] Sync enforcement. Updating to 116.47\n";
+    const FILLER: &str = "2024.07.22 17:48:44 Log        -  [Behaviour] Some unrelated line that VRChat prints constantly\n";
+
+    fn scan(path: &Path) -> UrlAndSeekResult {
+        VrcLogReader::new(
+            path.to_path_buf(),
+            LogMatcher::new(builtin_player_definitions(), None),
+        )
+        .get_latest_url_and_seek()
+    }
+
+    #[test]
+    fn it_pairs_the_url_with_the_same_players_seek() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output_log.txt");
+        let contents = [JOIN, URL_A, SEEK_A, FILLER, SEEK_B, FILLER].concat();
+        fs::write(&path, &contents).unwrap();
+
+        match scan(&path) {
+            UrlAndSeekResult::UrlAndSeek(found_url, found_seek, bytes_read_initially) => {
+                assert_eq!(found_url.url, "https://youtu.be/zL3wWykAKfs");
+                assert_eq!(found_seek.player_name, "ProTV Hangout (1)");
+                assert_eq!(found_seek.seek_offset, 44.96499);
+                assert_eq!(bytes_read_initially, contents.len() as u64);
+            }
+            _ => panic!("expected a URL and a seek"),
+        }
+    }
+
+    #[test]
+    fn it_stops_at_the_last_world_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output_log.txt");
+        fs::write(&path, [JOIN, URL_A, SEEK_A, JOIN, FILLER].concat()).unwrap();
+
+        assert!(matches!(scan(&path), UrlAndSeekResult::Nothing(_)));
+    }
+
+    #[test]
+    fn it_finds_the_current_world() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output_log.txt");
+        let join_b = "2024.07.22 17:49:00 Log        -  [Behaviour] Joining wrld_4cf554b4-430c-4f8f-b53e-1f294eed230b:67890~region(eu)\n";
+        let name_b = "2024.07.22 17:49:01 Log        -  [Behaviour] Joining or Creating Room: Movie & Chill\n";
+        fs::write(&path, [JOIN, URL_A, join_b, name_b, FILLER].concat()).unwrap();
+
+        let (found_world_change, world_name) = VrcLogReader::new(
+            path.clone(),
+            LogMatcher::new(builtin_player_definitions(), None),
+        )
+        .get_current_world()
+        .unwrap();
+        assert_eq!(
+            found_world_change.world_id,
+            "wrld_4cf554b4-430c-4f8f-b53e-1f294eed230b"
+        );
+        assert_eq!(world_name.as_deref(), Some("Movie & Chill"));
+    }
+
+    #[test]
+    fn it_leaves_a_partial_last_line_for_the_watcher() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output_log.txt");
+        let complete = [JOIN, URL_A].concat();
+        fs::write(&path, [complete.as_str(), &SEEK_A[..40]].concat()).unwrap();
+
+        match scan(&path) {
+            UrlAndSeekResult::Url(_, bytes_read_initially) => {
+                assert_eq!(bytes_read_initially, complete.len() as u64);
+            }
+            _ => panic!("expected only a URL"),
+        }
+    }
+
+    #[test]
+    fn it_reads_across_block_boundaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output_log.txt");
+        // enough filler on both sides of the URL that the interesting lines straddle several blocks
+        let filler = FILLER.repeat(10_000);
+        let contents = [JOIN, &filler, URL_A, &filler, SEEK_A, &filler].concat();
+        fs::write(&path, &contents).unwrap();
+
+        match scan(&path) {
+            UrlAndSeekResult::UrlAndSeek(found_url, found_seek, bytes_read_initially) => {
+                assert_eq!(found_url.player_name, "ProTV Hangout (1)");
+                assert_eq!(found_seek.seek_offset, 44.96499);
+                assert_eq!(bytes_read_initially, contents.len() as u64);
+            }
+            _ => panic!("expected a URL and a seek"),
+        }
+    }
+
+    #[test]
+    fn it_handles_a_file_without_complete_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output_log.txt");
+        fs::write(&path, &URL_A[..20]).unwrap();
+
+        assert!(matches!(scan(&path), UrlAndSeekResult::Nothing(0)));
+    }
+}
+
+// Benchmarks for log processing. These are slow and some write a few hundred MB to the temp dir, so they're ignored by
+// default.
+// Run them with: cargo test --release -- --ignored --nocapture bench_
+#[cfg(test)]
+mod benches {
+    use std::{
+        fs::File,
+        io::{BufWriter, Write},
+        path::Path,
+        time::Instant,
+    };
+
+    use crate::vrc_log_reader::{
+        builtin_player_definitions, LogMatcher, LogTail, UrlAndSeekResult, VrcLogReader,
+        SEEK_REGEX, URL_REGEX, WORLD_CHANGE_REGEX,
+    };
+
+    const TARGET_SIZE: u64 = 300 * 1024 * 1024;
+    const JOIN: &str = "2024.07.22 17:40:00 Log        -  [Behaviour] Joining wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~region(us)\n";
+    const URL: &str = "2024.07.22 17:48:43 Log        -  [ATA | TVManagerV2 (ProTV Hangout (1))] [AVPro1080p] Now Playing: https://youtu.be/zL3wWykAKfs\n";
+    const SEEK: &str = "2024.07.22 17:48:44 Log        -  [ATA | TVManagerV2 (ProTV Hangout (1))] Sync enforcement requested. Updating to 44.96499\n";
+    const FILLERS: [&str; 3] = [
+        "2024.07.22 17:48:44 Log        -  [Behaviour] OnPlayerJoined Some Username\n",
+        "2024.07.22 17:48:44 Warning    -  [Network Processing] RPC invoked on a destroyed object: VRCPlayer[Local] 12345\n",
+        "2024.07.22 17:48:44 Debug      -  [ATA | TVManagerV2 (ProTV Hangout (1))] Owner is Some Username\n",
+    ];
+
+    // Writes a synthetic log of about TARGET_SIZE bytes. The video starts `url_at` of the way through the file.
+    fn write_synthetic_log(path: &Path, url_at: f64) {
+        let mut writer = BufWriter::new(File::create(path).unwrap());
+        let url_at = (TARGET_SIZE as f64 * url_at) as u64;
+        let mut written: u64 = 0;
+        let mut wrote_url = false;
+        writer.write_all(JOIN.as_bytes()).unwrap();
+        let mut i = 0;
+        while written < TARGET_SIZE {
+            if !wrote_url && written >= url_at {
+                writer.write_all(URL.as_bytes()).unwrap();
+                wrote_url = true;
+            }
+            let line = if i % 5000 == 4999 && wrote_url {
+                SEEK
+            } else {
+                FILLERS[i % FILLERS.len()]
+            };
+            writer.write_all(line.as_bytes()).unwrap();
+            written += line.len() as u64;
+            i += 1;
+        }
+    }
+
+    // Roughly the old approach: stream the whole file forwards and run every regex on every line. (It actually took
+    // two of these passes, one for URLs and one for seeks.)
+    fn forward_scan(path: &Path) -> (u64, bool) {
+        let mut tail = LogTail::open(path, 0).unwrap();
+        let mut found = false;
+        tail.read_new_lines(|line, _| {
+            found |= URL_REGEX.captures(line).is_some();
+            found |= SEEK_REGEX.captures(line).is_some();
+        })
+        .unwrap();
+        (tail.offset, found)
+    }
+
+    fn run(url_at: f64) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output_log.txt");
+        write_synthetic_log(&path, url_at);
+        let megabytes = std::fs::metadata(&path).unwrap().len() as f64 / 1024.0 / 1024.0;
+
+        let start = Instant::now();
+        let (_, found) = forward_scan(&path);
+        let forward_elapsed = start.elapsed();
+        assert!(found);
+
+        let start = Instant::now();
+        let result = VrcLogReader::new(
+            path.clone(),
+            LogMatcher::new(builtin_player_definitions(), None),
+        )
+        .get_latest_url_and_seek();
+        let backward_elapsed = start.elapsed();
+        assert!(matches!(result, UrlAndSeekResult::UrlAndSeek(..)));
+
+        println!(
+            "{:.0} MB log, video started {:.0}% of the way in: forward scan {:?}, backward scan {:?}",
+            megabytes,
+            url_at * 100.0,
+            forward_elapsed,
+            backward_elapsed
+        );
+    }
+
+    // Compares running every regex on every line against the literal prefilter in LogMatcher, on lines already in memory.
+    #[test]
+    #[ignore]
+    fn bench_line_matching() {
+        let mut lines = Vec::new();
+        for i in 0..2_000_000 {
+            lines.push(match i % 1000 {
+                0 => URL,
+                500 => SEEK,
+                _ => FILLERS[i % FILLERS.len()],
+            });
+        }
+        let megabytes = lines.iter().map(|line| line.len()).sum::<usize>() as f64 / 1024.0 / 1024.0;
+
+        let start = Instant::now();
+        let mut naive_found = 0;
+        for line in &lines {
+            naive_found += URL_REGEX.captures(line).is_some() as usize;
+            naive_found += SEEK_REGEX.captures(line).is_some() as usize;
+            naive_found += WORLD_CHANGE_REGEX.captures(line).is_some() as usize;
+        }
+        let naive_elapsed = start.elapsed();
+
+        let matcher = LogMatcher::new(builtin_player_definitions(), None);
+        let start = Instant::now();
+        let mut prefiltered_found = 0;
+        for line in &lines {
+            prefiltered_found += matcher.match_line(line).is_some() as usize;
+        }
+        let prefiltered_elapsed = start.elapsed();
+        assert_eq!(naive_found, prefiltered_found);
+
+        let lines_per_sec =
+            |elapsed: std::time::Duration| lines.len() as f64 / elapsed.as_secs_f64();
+        println!(
+            "{} lines ({:.0} MB): every regex {:?} ({:.0} lines/s), prefiltered {:?} ({:.0} lines/s), {:.1}x faster",
+            lines.len(),
+            megabytes,
+            naive_elapsed,
+            lines_per_sec(naive_elapsed),
+            prefiltered_elapsed,
+            lines_per_sec(prefiltered_elapsed),
+            naive_elapsed.as_secs_f64() / prefiltered_elapsed.as_secs_f64()
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_recent_video() {
+        run(0.99);
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_old_video() {
+        run(0.01);
+    }
+}
+
+#[cfg(test)]
+mod log_matcher {
+    use regex::Regex;
+
+    use crate::vrc_log_reader::{
+        builtin_player_definitions, LogMatcher, PlayerDefinition, VrcLogWatcherEvent,
+    };
+
+    const URL: &str = "2024.07.22 17:48:43 Log        -  [ATA | TVManagerV2 (ProTV Hangout (1))] [AVPro1080p] Now Playing: https://youtu.be/zL3wWykAKfs";
+    const SEEK: &str = "2024.06.03 18:03:02 Log        -  [AT DEBUG  TVManager (Theatre 3 TVManager)] Jumping [VideoManager_Theatre3] to timestamp: 171.1321";
+    const JOIN: &str = "2024.07.22 17:40:00 Log        -  [Behaviour] Joining wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~region(us)";
+
+    fn matcher(player_name_regex: Option<&str>) -> LogMatcher {
+        LogMatcher::new(
+            builtin_player_definitions(),
+            player_name_regex.map(|regex| Regex::new(regex).unwrap()),
+        )
+    }
+
+    #[test]
+    fn it_matches_each_kind_of_line() {
+        let matcher = matcher(None);
+        match matcher.match_line(URL) {
+            Some(VrcLogWatcherEvent::FoundUrl(found_url)) => {
+                assert_eq!(found_url.url, "https://youtu.be/zL3wWykAKfs");
+                assert_eq!(found_url.player_name, "ProTV Hangout (1)");
+            }
+            _ => panic!("expected a URL"),
+        }
+        match matcher.match_line(SEEK) {
+            Some(VrcLogWatcherEvent::FoundSeek(found_seek)) => {
+                assert_eq!(found_seek.seek_offset, 171.1321);
+                assert_eq!(found_seek.player_name, "Theatre 3 TVManager");
+            }
+            _ => panic!("expected a seek"),
+        }
+        match matcher.match_line(JOIN) {
+            Some(VrcLogWatcherEvent::FoundWorldChange(found_world_change)) => {
+                assert_eq!(
+                    found_world_change.world_id,
+                    "wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd"
+                );
+            }
+            _ => panic!("expected a world change"),
+        }
+        let name =
+            "2024.07.22 17:40:01 Log        -  [Behaviour] Joining or Creating Room: Movie & Chill";
+        match matcher.match_line(name) {
+            Some(VrcLogWatcherEvent::FoundWorldName(found_world_name)) => {
+                assert_eq!(found_world_name.world_name, "Movie & Chill");
+            }
+            _ => panic!("expected a world name"),
+        }
+    }
+
+    #[test]
+    fn it_ignores_lines_that_only_contain_the_needle() {
+        let matcher = matcher(None);
+        let line = "2024.07.22 17:48:44 Debug      -  [ATA | TVManagerV2 (ProTV Hangout (1))] Owner is Some Username";
+        assert!(matcher.match_line(line).is_none());
+    }
+
+    #[test]
+    fn it_filters_by_player_name() {
+        let matcher = matcher(Some("^Theatre"));
+        assert!(matcher.match_line(URL).is_none());
+        assert!(matcher.match_line(SEEK).is_some());
+        // world changes aren't tied to any player
+        assert!(matcher.match_line(JOIN).is_some());
+    }
+
+    #[test]
+    fn it_only_follows_players_that_match_every_filter() {
+        let matcher = matcher(Some("^Theatre"));
+        let narrowed = matcher.with_player_name_regex(Some(Regex::new("Hangout").unwrap()));
+        assert!(narrowed.match_line(URL).is_none());
+        assert!(narrowed.match_line(SEEK).is_none());
+        let narrowed = matcher.with_player_name_regex(Some(Regex::new("3").unwrap()));
+        assert!(narrowed.match_line(SEEK).is_some());
+        // and the original is left alone
+        assert!(matcher.match_line(SEEK).is_some());
+    }
+
+    #[test]
+    fn it_skips_lines_a_loose_player_definition_cant_make_sense_of() {
+        // The example from the README, which captures more than it should, given the chance.
+        let matcher = LogMatcher::new(
+            vec![PlayerDefinition {
+                name: "USharpVideo".to_string(),
+                needle: "[USharpVideo]".to_string(),
+                url_regex: Regex::new(r"^(?P<timestamp>[0-9.: ]+) .*\[USharpVideo\] \((?P<player_name>.*)\) Started video: (?P<url>\S+)").unwrap(),
+                seek_regex: Regex::new(r"^(?P<timestamp>[0-9.: ]+) .*\[USharpVideo\] \((?P<player_name>.*)\) Seeked to (?P<new_offset>[0-9.]+)").unwrap(),
+            }],
+            None,
+        );
+        assert!(matcher
+            .match_line("2024.07.22 17:48:43 Log        -  [USharpVideo] (Screen) Seeked to 1.2.3")
+            .is_none());
+        assert!(matcher
+            .match_line("2024.07.22 17:48: Log        -  [USharpVideo] (Screen) Started video: https://youtu.be/zL3wWykAKfs")
+            .is_none());
+        assert!(matcher
+            .match_line("2024.07.22 17:48:43 Log        -  [USharpVideo] (Screen) Seeked to 12.5")
+            .is_some());
+    }
+
+    #[test]
+    fn it_tries_the_next_player_definition_if_one_doesnt_follow_the_line() {
+        // Going by the first one, the line is from a player called "USharpVideo", which isn't followed.
+        let matcher = LogMatcher::new(
+            vec![
+                PlayerDefinition {
+                    name: "Anything".to_string(),
+                    needle: "Started video".to_string(),
+                    url_regex: Regex::new(r"^(?P<timestamp>[0-9.]+ [0-9:]+) .*\[(?P<player_name>[^\]]+)\] .*Started video: (?P<url>\S+)").unwrap(),
+                    seek_regex: Regex::new(r"^(?P<timestamp>[0-9.]+ [0-9:]+) .*\[(?P<player_name>[^\]]+)\] .*Seeked to (?P<new_offset>[0-9.]+)").unwrap(),
+                },
+                PlayerDefinition {
+                    name: "USharpVideo".to_string(),
+                    needle: "[USharpVideo]".to_string(),
+                    url_regex: Regex::new(r"^(?P<timestamp>[0-9.: ]+) .*\[USharpVideo\] \((?P<player_name>.*)\) Started video: (?P<url>\S+)").unwrap(),
+                    seek_regex: Regex::new(r"^(?P<timestamp>[0-9.: ]+) .*\[USharpVideo\] \((?P<player_name>.*)\) Seeked to (?P<new_offset>[0-9.]+)").unwrap(),
+                },
+            ],
+            Some(Regex::new("^Screen$").unwrap()),
+        );
+        match matcher.match_line("2024.07.22 17:48:43 Log        -  [USharpVideo] (Screen) Started video: https://youtu.be/zL3wWykAKfs") {
+            Some(VrcLogWatcherEvent::FoundUrl(found_url)) => {
+                assert_eq!(found_url.player_name, "Screen");
+            }
+            _ => panic!("expected a URL"),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::{
+    env,
+    fs::{self, DirBuilder},
+    io,
+    os::unix::fs::{DirBuilderExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use crate::log_warn;
+
+const APP_DIR_NAME: &str = "vrc-avpro-sucks";
+
+// Where to keep data that should survive a restart, but isn't worth backing up. Per the XDG Base Directory spec, that's
+// $XDG_STATE_HOME, falling back to ~/.local/state.
+pub(crate) fn state_dir() -> PathBuf {
+    base_dir("XDG_STATE_HOME", ".local/state").join(APP_DIR_NAME)
+}
+
+// Where the user keeps settings, i.e. $XDG_CONFIG_HOME, falling back to ~/.config.
+pub(crate) fn config_dir() -> PathBuf {
+    base_dir("XDG_CONFIG_HOME", ".config").join(APP_DIR_NAME)
+}
+
+// Where to put sockets and other files that only make sense while we're running. Per the spec, that's $XDG_RUNTIME_DIR,
+// which has no fallback under $HOME, so we make do with the state dir if it's missing.
+pub(crate) fn runtime_dir() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir.join(APP_DIR_NAME),
+        _ => {
+            log_warn!("XDG_RUNTIME_DIR isn't set, so using the state directory for runtime files instead.");
+            state_dir().join("run")
+        }
+    }
+}
+
+// Creates the directory if needed, and makes sure nobody else can get into it, even if it already existed.
+pub(crate) fn create_private_dir(dir: &Path) -> io::Result<()> {
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+}
+
+fn base_dir(env_var: &str, fallback_under_home: &str) -> PathBuf {
+    match env::var_os(env_var).map(PathBuf::from) {
+        // the spec says relative paths are invalid and should be ignored
+        Some(dir) if dir.is_absolute() => dir,
+        _ => PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(fallback_under_home),
+    }
+}